@@ -0,0 +1,291 @@
+// compare.rs
+use std::{collections::{HashMap, HashSet}, fs::File, io::{BufRead, BufReader, Write}, path::{Path, PathBuf}};
+use crate::output::{OutputArgs, OutputPaths, Provenance};
+
+#[derive(clap::Args, Debug)]
+pub struct CompareArgs {
+    /// Gene-level operons table ('_operon_genes_' TSV) of the first species or population.
+    #[arg(long)]
+    operons_a: PathBuf,
+
+    /// Gene-level operons table ('_operon_genes_' TSV) of the second species or population.
+    #[arg(long)]
+    operons_b: PathBuf,
+
+    /// Orthology table: TSV with a gene of the first species in the first column and its
+    /// ortholog in the second one (one pair per line; many-to-many allowed).
+    #[arg(long)]
+    orthologs: PathBuf,
+
+    /// Name of the first species in the outputs.
+    #[arg(long, default_value = "A")]
+    name_a: String,
+
+    /// Name of the second species in the outputs.
+    #[arg(long, default_value = "B")]
+    name_b: String,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+/// Conservation of a pair of consecutive OpGs in the other species.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairStatus {
+    /// Orthologs of both genes are consecutive in an OPRN of the other species.
+    Conserved,
+    /// Both genes have orthologs, never consecutive in an OPRN of the other species.
+    NotConserved,
+    /// At least one of the genes has no ortholog.
+    NoOrtholog,
+}
+
+impl std::fmt::Display for PairStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            PairStatus::Conserved => "conserved",
+            PairStatus::NotConserved => "not_conserved",
+            PairStatus::NoOrtholog => "no_ortholog",
+        })
+    }
+}
+
+/// A pair of consecutive OpGs and its orthologous pair, when conserved.
+#[derive(Debug, Clone)]
+pub struct PairConservation {
+    pub operon_id: String,
+    pub genes: (String, String),
+    /// Orthologous pair and its OPRN in the other species.
+    pub orthologous: Option<(String, String, String)>,
+    pub status: PairStatus,
+}
+
+/// Conservation of an OPRN from the conservation of its pairs of consecutive OpGs.
+#[derive(Debug, Clone)]
+pub struct OperonConservation {
+    pub operon_id: String,
+    pub genes: Vec<String>,
+    pub pairs: Vec<PairConservation>,
+}
+
+impl OperonConservation {
+    /// 'conserved' when every pair is, 'partial' when some are, 'lineage_specific' when none is
+    /// although some could be assessed, 'no_orthologs' when no pair has orthologs on both genes.
+    pub fn status(&self) -> &'static str {
+        let conserved = self.pairs.iter().filter(|p| p.status == PairStatus::Conserved).count();
+        if conserved > 0 && conserved == self.pairs.len() {
+            "conserved"
+        } else if conserved > 0 {
+            "partial"
+        } else if self.pairs.iter().any(|p| p.status == PairStatus::NotConserved) {
+            "lineage_specific"
+        } else {
+            "no_orthologs"
+        }
+    }
+
+    /// OPRNs of the other species holding its conserved pairs.
+    pub fn orthologous_operons(&self) -> Vec<&str> {
+        let mut operons: Vec<&str> = Vec::new();
+        for (_, _, operon_id) in self.pairs.iter().filter_map(|p| p.orthologous.as_ref()) {
+            if !operons.contains(&operon_id.as_str()) {
+                operons.push(operon_id);
+            }
+        }
+        operons
+    }
+}
+
+/// Reads a gene-level operons table: the genes of every OPRN by rank, OPRNs in table order.
+pub fn read_operon_genes(path: &Path) -> anyhow::Result<Vec<(String, Vec<String>)>> {
+    let mut columns = None;
+    let mut operons: Vec<(String, Vec<(usize, String)>)> = Vec::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if line.starts_with("Operon\t") {
+            let column = |name: &str| fields.iter().position(|c| *c == name);
+            columns = Some((column("Gene_rank"), column("Gene_id")));
+            continue;
+        }
+        let Some((Some(rank_column), Some(gene_column))) = columns else {
+            anyhow::bail!("{}: expected a gene-level operons table ('_operon_genes_' TSV) with Gene_rank and Gene_id columns", path.display());
+        };
+        let (Some(rank), Some(gene_id)) = (fields.get(rank_column).and_then(|r| r.parse::<usize>().ok()), fields.get(gene_column)) else {
+            anyhow::bail!("{}:{}: invalid or missing Gene_rank/Gene_id", path.display(), i + 1);
+        };
+        match operons.last_mut() {
+            Some((operon_id, genes)) if operon_id == fields[0] => genes.push((rank, gene_id.to_string())),
+            _ => operons.push((fields[0].to_string(), vec![(rank, gene_id.to_string())])),
+        }
+    }
+    Ok(operons
+        .into_iter()
+        .map(|(operon_id, mut genes)| {
+            genes.sort();
+            (operon_id, genes.into_iter().map(|(_, gene_id)| gene_id).collect())
+        })
+        .collect())
+}
+
+// Orthologs of every gene
+type Orthologs = HashMap<String, Vec<String>>;
+
+/// Reads an orthology table into the orthologs of every gene, in both directions.
+pub fn read_orthologs(path: &Path) -> anyhow::Result<(Orthologs, Orthologs)> {
+    let mut a_to_b: Orthologs = HashMap::new();
+    let mut b_to_a: Orthologs = HashMap::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 2 {
+            anyhow::bail!("{}:{}: expected two gene IDs", path.display(), i + 1);
+        }
+        a_to_b.entry(fields[0].to_string()).or_default().push(fields[1].to_string());
+        b_to_a.entry(fields[1].to_string()).or_default().push(fields[0].to_string());
+    }
+    Ok((a_to_b, b_to_a))
+}
+
+/// Conservation of the OPRNs of one species in the other: a pair of consecutive OpGs is
+/// conserved when orthologs of its genes are consecutive, in either order, in an OPRN of the
+/// other species.
+pub fn conservation(operons: &[(String, Vec<String>)], other: &[(String, Vec<String>)], orthologs: &Orthologs) -> Vec<OperonConservation> {
+    let mut other_pairs: HashMap<(&str, &str), &str> = HashMap::new();
+    for (operon_id, genes) in other {
+        for pair in genes.windows(2) {
+            other_pairs.entry((pair[0].as_str(), pair[1].as_str())).or_insert(operon_id);
+            other_pairs.entry((pair[1].as_str(), pair[0].as_str())).or_insert(operon_id);
+        }
+    }
+    let no_orthologs = Vec::new();
+    operons
+        .iter()
+        .map(|(operon_id, genes)| {
+            let pairs = genes
+                .windows(2)
+                .map(|pair| {
+                    let orthologs_1 = orthologs.get(&pair[0]).unwrap_or(&no_orthologs);
+                    let orthologs_2 = orthologs.get(&pair[1]).unwrap_or(&no_orthologs);
+                    let orthologous = orthologs_1
+                        .iter()
+                        .flat_map(|o1| orthologs_2.iter().map(move |o2| (o1, o2)))
+                        .find_map(|(o1, o2)| other_pairs.get(&(o1.as_str(), o2.as_str())).map(|other_id| (o1.clone(), o2.clone(), other_id.to_string())));
+                    let status = match orthologous {
+                        Some(_) => PairStatus::Conserved,
+                        None if orthologs_1.is_empty() || orthologs_2.is_empty() => PairStatus::NoOrtholog,
+                        None => PairStatus::NotConserved,
+                    };
+                    PairConservation { operon_id: operon_id.clone(), genes: (pair[0].clone(), pair[1].clone()), orthologous, status }
+                })
+                .collect();
+            OperonConservation { operon_id: operon_id.clone(), genes: genes.clone(), pairs }
+        })
+        .collect()
+}
+
+// Metrics of the conservation summary: OPRNs by status and conservation of the pairs of
+// consecutive OpGs
+fn summary_rows(operons: &[OperonConservation]) -> Vec<(&'static str, String)> {
+    let pairs: Vec<&PairConservation> = operons.iter().flat_map(|o| &o.pairs).collect();
+    let count_pairs = |status: PairStatus| pairs.iter().filter(|p| p.status == status).count();
+    let count_operons = |status: &str| operons.iter().filter(|o| o.status() == status).count().to_string();
+    let assessed = pairs.len() - count_pairs(PairStatus::NoOrtholog);
+    let genes: HashSet<&str> = operons.iter().flat_map(|o| o.genes.iter().map(|g| g.as_str())).collect();
+    vec![
+        ("OPRNs", operons.len().to_string()),
+        ("OpGs", genes.len().to_string()),
+        ("Conserved", count_operons("conserved")),
+        ("Partially_conserved", count_operons("partial")),
+        ("Lineage_specific", count_operons("lineage_specific")),
+        ("No_orthologs", count_operons("no_orthologs")),
+        ("Gene_pairs", pairs.len().to_string()),
+        ("Pairs_with_orthologs", assessed.to_string()),
+        ("Conserved_pairs", count_pairs(PairStatus::Conserved).to_string()),
+        (
+            "Pair_conservation",
+            if assessed == 0 { "NA".to_string() } else { format!("{:.4}", count_pairs(PairStatus::Conserved) as f64 / assessed as f64) },
+        ),
+    ]
+}
+
+/// Compares the OPRNs of two species or populations through an orthology table.
+pub fn run(args: CompareArgs) -> anyhow::Result<()> {
+    let mut out = OutputPaths::new(&args.operons_a, &args.output)?;
+    out.init_log()?;
+    out.provenance = Some(Provenance::new(&args.operons_a, &[
+        ("operons_b", args.operons_b.display().to_string()),
+        ("orthologs", args.orthologs.display().to_string()),
+        ("name_a", args.name_a.clone()),
+        ("name_b", args.name_b.clone()),
+    ])?);
+
+    let operons_a = read_operon_genes(&args.operons_a)?;
+    let operons_b = read_operon_genes(&args.operons_b)?;
+    let (a_to_b, b_to_a) = read_orthologs(&args.orthologs)?;
+    log::info!("Read {} OPRNs of {} from {}", operons_a.len(), args.name_a, args.operons_a.display());
+    log::info!("Read {} OPRNs of {} from {}", operons_b.len(), args.name_b, args.operons_b.display());
+    log::info!("Read orthologs of {} {} genes and {} {} genes from {}", a_to_b.len(), args.name_a, b_to_a.len(), args.name_b, args.orthologs.display());
+    let results = [
+        (args.name_a.as_str(), conservation(&operons_a, &operons_b, &a_to_b)),
+        (args.name_b.as_str(), conservation(&operons_b, &operons_a, &b_to_a)),
+    ];
+
+    let operons_path = format!("{}_conserved_operons.tsv", out.prefix);
+    let mut operons_file = out.create(operons_path.clone())?;
+    writeln!(operons_file, "Species\tOperon\tGenes\tPairs\tConserved_pairs\tOrthologous_operons\tStatus")?;
+    let pairs_path = format!("{}_conserved_pairs.tsv", out.prefix);
+    let mut pairs_file = out.create(pairs_path.clone())?;
+    writeln!(pairs_file, "Species\tOperon\tGene_1\tGene_2\tOrtholog_1\tOrtholog_2\tOrthologous_operon\tStatus")?;
+    for (species, operons) in &results {
+        for operon in operons {
+            let orthologous = operon.orthologous_operons();
+            writeln!(
+                operons_file,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                species,
+                operon.operon_id,
+                operon.genes.join(","),
+                operon.pairs.len(),
+                operon.pairs.iter().filter(|p| p.status == PairStatus::Conserved).count(),
+                if orthologous.is_empty() { ".".to_string() } else { orthologous.join(",") },
+                operon.status()
+            )?;
+            for pair in &operon.pairs {
+                let (o1, o2, other_id) = pair.orthologous.clone().unwrap_or_else(|| (".".into(), ".".into(), ".".into()));
+                writeln!(pairs_file, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", species, pair.operon_id, pair.genes.0, pair.genes.1, o1, o2, other_id, pair.status)?;
+            }
+        }
+    }
+
+    let summary_path = format!("{}_conservation_summary.tsv", out.prefix);
+    let mut summary_file = out.create(summary_path.clone())?;
+    writeln!(summary_file, "Metric\t{}\t{}", args.name_a, args.name_b)?;
+    let (summary_a, summary_b) = (summary_rows(&results[0].1), summary_rows(&results[1].1));
+    for ((metric, value_a), (_, value_b)) in summary_a.iter().zip(&summary_b) {
+        writeln!(summary_file, "{}\t{}\t{}", metric, value_a, value_b)?;
+    }
+
+    for (species, operons) in &results {
+        let count = |status: &str| operons.iter().filter(|o| o.status() == status).count();
+        log::info!(
+            "{}: {} OPRNs, {} conserved, {} partially conserved, {} lineage-specific, {} without orthologs",
+            species,
+            operons.len(),
+            count("conserved"),
+            count("partial"),
+            count("lineage_specific"),
+            count("no_orthologs")
+        );
+    }
+    log::info!("Operon conservation written to {}", operons_path);
+    log::info!("Gene-pair conservation written to {}", pairs_path);
+    log::info!("Summary written to {}", summary_path);
+    Ok(())
+}
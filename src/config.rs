@@ -0,0 +1,115 @@
+// config.rs
+use std::path::Path;
+use clap::{ArgMatches, ValueEnum, parser::ValueSource};
+use serde::Deserialize;
+use crate::detect::{ConflictPolicy, Objective, Params, Selection};
+
+/// Detection parameters read from a TOML file; missing keys keep their previous value.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ParamsFile {
+    pub threshold: Option<f32>,
+    pub monoexonic_t: Option<f32>,
+    pub min_overlap: Option<f32>,
+    pub bp_overlap: Option<u64>,
+    pub tolerance: Option<u64>,
+    pub opg_tolerance: Option<u64>,
+    pub conflict_policy: Option<ConflictPolicy>,
+    pub selection: Option<Selection>,
+    pub objective: Option<Objective>,
+}
+
+impl ParamsFile {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        toml::from_str(&content).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    }
+}
+
+/// Built-in parameter sets; they are starting points to be refined with a sweep.
+///
+/// Only the Oikopleura values are gamba's tested defaults. The nematode and long-read values
+/// are illustrative guesses scaled from them, not fitted to any dataset.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Compact genomes with short intergenic distances, as Oikopleura dioica (gamba defaults).
+    #[value(alias = "compact-genome")]
+    Oikopleura,
+    /// Nematode SL2-type operons, with short intercistronic regions (illustrative values).
+    Nematode,
+    /// Long-read assemblies, with precise transcript ends and lower coverage (illustrative values).
+    LongRead,
+}
+
+impl Preset {
+    pub fn values(&self) -> ParamsFile {
+        match self {
+            Preset::Oikopleura => ParamsFile {
+                threshold: Some(1.0),
+                monoexonic_t: Some(10.0),
+                min_overlap: Some(0.5),
+                bp_overlap: Some(50),
+                tolerance: Some(250),
+                opg_tolerance: Some(50),
+                ..Default::default()
+            },
+            Preset::Nematode => ParamsFile {
+                threshold: Some(1.0),
+                monoexonic_t: Some(5.0),
+                min_overlap: Some(0.5),
+                bp_overlap: Some(30),
+                tolerance: Some(150),
+                opg_tolerance: Some(30),
+                ..Default::default()
+            },
+            Preset::LongRead => ParamsFile {
+                threshold: Some(0.5),
+                monoexonic_t: Some(5.0),
+                min_overlap: Some(0.75),
+                bp_overlap: Some(100),
+                tolerance: Some(100),
+                opg_tolerance: Some(20),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Applies the config file, then the preset, to every parameter not given on the command
+/// line, and logs the effective value of each one with its origin.
+pub fn resolve(mut params: Params, matches: &ArgMatches, config: Option<&ParamsFile>, preset: Option<Preset>) -> Params {
+    let layers: Vec<(&str, ParamsFile)> = [
+        config.map(|c| ("config", c.clone())),
+        preset.map(|p| ("preset", p.values())),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    macro_rules! resolve_param {
+        ($field:ident) => {
+            let mut source = match matches.value_source(stringify!($field)) {
+                Some(ValueSource::CommandLine) => "command line",
+                _ => "default",
+            };
+            if source == "default" {
+                if let Some((layer, value)) = layers.iter().find_map(|(layer, values)| values.$field.map(|v| (*layer, v))) {
+                    params.$field = value;
+                    source = layer;
+                }
+            }
+            log::info!("Parameter {} = {:?} ({})", stringify!($field), params.$field, source);
+        };
+    }
+
+    resolve_param!(threshold);
+    resolve_param!(monoexonic_t);
+    resolve_param!(min_overlap);
+    resolve_param!(bp_overlap);
+    resolve_param!(tolerance);
+    resolve_param!(opg_tolerance);
+    resolve_param!(conflict_policy);
+    resolve_param!(selection);
+    resolve_param!(objective);
+    params
+}
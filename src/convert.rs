@@ -0,0 +1,101 @@
+// convert.rs
+use std::{fs::File, io::{BufWriter, Write}, path::PathBuf};
+use clap::ValueEnum;
+use crate::gtf::{self, Transcript};
+use crate::output::Provenance;
+
+#[derive(clap::Args, Debug)]
+pub struct ConvertArgs {
+    /// Path to the input GTF file.
+    #[arg(short, long)]
+    file: PathBuf,
+
+    /// Output format.
+    #[arg(long, value_enum)]
+    to: Format,
+
+    /// Output file [default: standard output].
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Log and skip malformed GTF records instead of stopping at the first one.
+    #[arg(long)]
+    skip_invalid: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// BED12, one line per transcript with its exons as blocks.
+    Bed,
+    /// GFF3, transcripts with an 'ID' attribute and their exons as children.
+    Gff3,
+}
+
+fn write_bed(out: &mut dyn Write, transcript: &Transcript) -> anyhow::Result<()> {
+    // A transcript without exon records is a single block
+    let mut exons = if transcript.exons.is_empty() { vec![(transcript.start, transcript.end)] } else { transcript.exons.clone() };
+    exons.sort();
+    // Exons may extend beyond the transcript record
+    let start = transcript.start.min(exons[0].0);
+    let end = exons.iter().map(|(_, e)| *e).max().unwrap_or(transcript.end).max(transcript.end);
+    let sizes: Vec<String> = exons.iter().map(|(s, e)| (e - s + 1).to_string()).collect();
+    let starts: Vec<String> = exons.iter().map(|(s, _)| (s - start).to_string()).collect();
+    writeln!(
+        out,
+        "{}\t{}\t{}\t{}\t0\t{}\t{}\t{}\t0\t{}\t{},\t{},",
+        transcript.chrom,
+        start - 1,
+        end,
+        transcript.id,
+        gtf::strand_symbol(&transcript.strand),
+        start - 1,
+        end,
+        exons.len(),
+        sizes.join(","),
+        starts.join(","),
+    )?;
+    Ok(())
+}
+
+// Original GFF lines of the transcript, with 'ID' (transcript) or 'Parent' (exons) prepended to the attributes
+fn write_gff3(out: &mut dyn Write, transcript: &Transcript) -> anyhow::Result<()> {
+    for line in &transcript.raw_lines {
+        let line = line.trim_end();
+        let Some((fields, attributes)) = line.rsplit_once('\t') else {
+            continue;
+        };
+        let parents = if fields.split('\t').nth(2) == Some("transcript") {
+            format!("ID={}", transcript.id)
+        } else {
+            format!("Parent={}", transcript.id)
+        };
+        writeln!(out, "{}\t{};{}", fields, parents, attributes)?;
+    }
+    Ok(())
+}
+
+/// Writes every transcript of the input GTF in the requested format, ordered by position.
+pub fn run(args: ConvertArgs) -> anyhow::Result<()> {
+    let annotation = gtf::read_annotation(&args.file, args.skip_invalid)?;
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(std::io::stdout())),
+    };
+
+    if args.to == Format::Gff3 {
+        writeln!(out, "##gff-version 3")?;
+    }
+    Provenance::new(&args.file, &[("to", format!("{:?}", args.to))])?.write(&mut out)?;
+    for transcripts in annotation.transcripts_by_chrom.values() {
+        let mut ordered: Vec<&Transcript> = transcripts.iter().collect();
+        ordered.sort_by_key(|t| (t.start, t.end));
+        for transcript in ordered {
+            match args.to {
+                Format::Bed => write_bed(&mut out, transcript)?,
+                Format::Gff3 => write_gff3(&mut out, transcript)?,
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
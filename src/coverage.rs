@@ -0,0 +1,139 @@
+// coverage.rs
+use std::{collections::BTreeMap, fs::File, io::{BufRead, BufReader}, path::Path};
+use noodles::bam;
+use crate::gtf::Annotation;
+use crate::longread::aligned_blocks;
+
+/// Depth over the exons of one chromosome: merged exonic intervals laid end to end in `depth`.
+struct ExonicDepth {
+    // (start, end, offset in `depth`), 1-based inclusive
+    intervals: Vec<(u64, u64, usize)>,
+    depth: Vec<f64>,
+}
+
+impl ExonicDepth {
+    fn new(mut exons: Vec<(u64, u64)>) -> Self {
+        exons.sort();
+        let mut intervals: Vec<(u64, u64, usize)> = Vec::new();
+        let mut length = 0;
+        for (start, end) in exons {
+            match intervals.last_mut() {
+                Some(last) if start <= last.1 + 1 => {
+                    if end > last.1 {
+                        length += (end - last.1) as usize;
+                        last.1 = end;
+                    }
+                }
+                _ => {
+                    intervals.push((start, end, length));
+                    length += (end - start + 1) as usize;
+                }
+            }
+        }
+        // One more slot for the difference array
+        ExonicDepth { intervals, depth: vec![0.0; length + 1] }
+    }
+
+    // Adds `value` over start..=end, as differences until `finish`
+    fn add(&mut self, start: u64, end: u64, value: f64) {
+        let first = self.intervals.partition_point(|&(_, e, _)| e < start);
+        for &(s, e, offset) in self.intervals[first..].iter().take_while(|&&(s, _, _)| s <= end) {
+            let (from, to) = (start.max(s), end.min(e));
+            self.depth[offset + (from - s) as usize] += value;
+            self.depth[offset + (to - s) as usize + 1] -= value;
+        }
+    }
+
+    fn finish(&mut self) {
+        let mut running = 0.0;
+        for d in self.depth.iter_mut() {
+            running += *d;
+            *d = running;
+        }
+    }
+
+    // Summed depth over start..=end, which lies inside one interval
+    fn sum(&self, start: u64, end: u64) -> f64 {
+        let i = self.intervals.partition_point(|&(_, e, _)| e < start);
+        let Some(&(s, _, offset)) = self.intervals.get(i) else { return 0.0 };
+        let from = offset + (start - s) as usize;
+        self.depth[from..from + (end - start + 1) as usize].iter().sum()
+    }
+}
+
+/// Sets the coverage of every transcript to its mean per-base depth over its exons, from
+/// a BAM (primary, non-duplicate alignments; strand is ignored) or a bedGraph.
+pub fn fill_coverage(annotation: &mut Annotation, path: &Path) -> anyhow::Result<()> {
+    let mut depth_by_chrom: BTreeMap<String, ExonicDepth> = annotation
+        .transcripts_by_chrom
+        .iter()
+        .map(|(chrom, transcripts)| {
+            let exons = transcripts.iter().flat_map(|t| if t.exons.is_empty() { vec![(t.start, t.end)] } else { t.exons.clone() }).collect();
+            (chrom.clone(), ExonicDepth::new(exons))
+        })
+        .collect();
+
+    let name = path.to_string_lossy().to_lowercase();
+    if name.ends_with(".bam") {
+        let mut reader = bam::io::reader::Builder.build_from_path(path)?;
+        let header = reader.read_header()?;
+        let mut used_reads = 0;
+        for result in reader.records() {
+            let record = result?;
+            let flags = record.flags();
+            if flags.is_unmapped() || flags.is_secondary() || flags.is_supplementary() || flags.is_qc_fail() || flags.is_duplicate() {
+                continue;
+            }
+            let Some(reference_sequence_id) = record.reference_sequence_id().transpose()? else { continue };
+            let Some((chrom, _)) = header.reference_sequences().get_index(reference_sequence_id) else { continue };
+            let Some(depth) = depth_by_chrom.get_mut(&chrom.to_string()) else { continue };
+            used_reads += 1;
+            for (start, end) in aligned_blocks(&record)? {
+                depth.add(start, end, 1.0);
+            }
+        }
+        log::info!("Coverage from {}: {} alignments on annotated chromosomes", path.display(), used_reads);
+    } else if name.ends_with(".bw") || name.ends_with(".bigwig") {
+        anyhow::bail!("{}: bigWig is not supported, convert it with 'bigWigToBedGraph'", path.display());
+    } else {
+        for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 4 {
+                anyhow::bail!("{}:{}: expected a bedGraph line (chrom, start, end, value)", path.display(), i + 1);
+            }
+            let parse_error = || anyhow::anyhow!("{}:{}: invalid bedGraph line", path.display(), i + 1);
+            let start: u64 = fields[1].parse().map_err(|_| parse_error())?;
+            let end: u64 = fields[2].parse().map_err(|_| parse_error())?;
+            let value: f64 = fields[3].parse().map_err(|_| parse_error())?;
+            if end > start && let Some(depth) = depth_by_chrom.get_mut(fields[0]) {
+                depth.add(start + 1, end, value);
+            }
+        }
+    }
+
+    let mut with_attribute = 0;
+    let mut transcripts = 0;
+    for (chrom, chrom_transcripts) in annotation.transcripts_by_chrom.iter_mut() {
+        let depth = depth_by_chrom.get_mut(chrom).unwrap();
+        depth.finish();
+        for t in chrom_transcripts.iter_mut() {
+            let exons = if t.exons.is_empty() { vec![(t.start, t.end)] } else { t.exons.clone() };
+            let length: u64 = exons.iter().map(|(s, e)| e - s + 1).sum();
+            let total: f64 = exons.iter().map(|&(s, e)| depth.sum(s, e)).sum();
+            if t.coverage != 0.0 {
+                with_attribute += 1;
+            }
+            t.coverage = (total / length as f64) as f32;
+            transcripts += 1;
+        }
+    }
+    if with_attribute > 0 {
+        log::warn!("{} of {} transcripts had a 'cov' attribute, replaced by the coverage from {}", with_attribute, transcripts, path.display());
+    }
+    log::info!("Mean exonic coverage of {} transcripts computed from {}", transcripts, path.display());
+    Ok(())
+}
@@ -0,0 +1,891 @@
+// detect.rs
+use std::{cmp::Ordering, collections::{BTreeMap, HashMap, HashSet}, fs::File, io::{BufRead, BufReader}, path::Path};
+use clap::ValueEnum;
+use serde::Deserialize;
+use itertools::Itertools;
+use crate::gtf::{self, Transcript};
+
+type GeneId = String;
+// (container gene_id, container, contained transcript)
+type OperonClaim<'a> = (GeneId, &'a Transcript, &'a Transcript);
+
+/// Extent of the synthetic operon features.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OperonSpan {
+    /// From the start of the first OpG to the end of the last one.
+    #[default]
+    Opgs,
+    /// Span of the containers of the OPRN.
+    Containers,
+}
+
+/// Expression value of a transcript used by the expression analyses.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Measure {
+    #[default]
+    Fpkm,
+    Coverage,
+}
+
+impl Measure {
+    pub fn value(self, transcript: &Transcript) -> f32 {
+        match self {
+            Measure::Fpkm => transcript.fpkm_val,
+            Measure::Coverage => transcript.coverage,
+        }
+    }
+}
+
+/// How to assign an OpG that several containers claim.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    /// Keep the first container by genomic start (historical behaviour).
+    First,
+    /// Keep the container with the highest coverage.
+    Coverage,
+    /// Keep the container with the longest span.
+    Longest,
+    /// Keep every claim; the OpG may belong to several containers/OPRNs.
+    KeepAll,
+}
+
+/// How overlapping OpG candidates are pruned.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Selection {
+    /// Compare each candidate only with the last kept one (historical behaviour).
+    Greedy,
+    /// Exact weighted interval scheduling, independent of candidate order.
+    Optimal,
+}
+
+/// Per-transcript value summed by the optimal selection.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Objective {
+    /// FPKM attribute.
+    Fpkm,
+    /// Coverage ('cov') attribute.
+    Coverage,
+    /// Number of exons.
+    Exons,
+    /// GTF score column.
+    Score,
+}
+
+impl Objective {
+    pub fn weight(&self, t: &Transcript) -> f64 {
+        match self {
+            Objective::Fpkm => t.fpkm_val as f64,
+            Objective::Coverage => t.coverage as f64,
+            Objective::Exons => t.exons.len() as f64,
+            Objective::Score => t.score as f64,
+        }
+    }
+}
+
+/// Parameters of a detection run.
+#[derive(Debug, Clone)]
+pub struct Params {
+    pub threshold: f32,
+    pub monoexonic_t: f32,
+    pub min_overlap: f32,
+    pub bp_overlap: u64,
+    /// Boundary tolerance (bp) for containment and for clustering containers into OPRNs.
+    pub tolerance: u64,
+    /// Overlap tolerance (bp) between OpGs.
+    pub opg_tolerance: u64,
+    pub conflict_policy: ConflictPolicy,
+    pub selection: Selection,
+    pub objective: Objective,
+}
+
+impl Params {
+    /// Name and value of every parameter, for logs and file headers.
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("threshold", self.threshold.to_string()),
+            ("monoexonic_t", self.monoexonic_t.to_string()),
+            ("min_overlap", self.min_overlap.to_string()),
+            ("bp_overlap", self.bp_overlap.to_string()),
+            ("tolerance", self.tolerance.to_string()),
+            ("opg_tolerance", self.opg_tolerance.to_string()),
+            ("conflict_policy", format!("{:?}", self.conflict_policy)),
+            ("selection", format!("{:?}", self.selection)),
+            ("objective", format!("{:?}", self.objective)),
+        ]
+    }
+}
+
+/// A container claiming an OpG that other containers also claim.
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub inner_id: String,
+    pub container_id: String,
+    pub operon: String,
+    pub container_cov: f32,
+    pub container_length: u64,
+    pub kept: bool,
+}
+
+/// Step of the detection at which a candidate container was accepted or rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// No transcript passed the containment test.
+    NoContained,
+    /// Fewer than two contained transcripts.
+    FewContained,
+    /// Contained by a higher-coverage isoform.
+    HigherCovIsoform,
+    /// Fewer than two OpGs left after removing overlapping ones.
+    OverlapPruning,
+    /// The OpGs do not have more exons than the container.
+    ExonGain,
+    /// Prokaryotic mode: no two covered genes close enough to each other.
+    IntergenicGap,
+    /// Passed every container test; resolved after clustering.
+    Claimed,
+    /// Every claimed OpG went to another container.
+    ClaimsLost,
+    /// Its OPRN kept fewer than two non-overlapping OpGs.
+    OprnPruning,
+    /// Its OPRN has consecutive OpGs bridged by fewer than '--min-bridging-reads' junction reads.
+    JunctionSupport,
+    /// Reported as an operon.
+    Operon,
+}
+
+impl std::fmt::Display for Decision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let code = match self {
+            Decision::NoContained => "no_contained",
+            Decision::FewContained => "few_contained",
+            Decision::HigherCovIsoform => "higher_cov_isoform",
+            Decision::OverlapPruning => "overlap_pruning",
+            Decision::ExonGain => "exon_gain",
+            Decision::IntergenicGap => "intergenic_gap",
+            Decision::Claimed => "claimed",
+            Decision::ClaimsLost => "claims_lost",
+            Decision::OprnPruning => "oprn_pruning",
+            Decision::JunctionSupport => "junction_support",
+            Decision::Operon => "operon",
+        };
+        write!(f, "{}", code)
+    }
+}
+
+/// Values tested for a transcript acting as container, and the resulting decision.
+#[derive(Debug, Clone)]
+pub struct ContainerTrace {
+    pub container_id: String,
+    pub chrom: String,
+    pub strand: String,
+    pub start: u64,
+    pub end: u64,
+    pub coverage: f32,
+    pub container_exons: usize,
+    /// Transcripts passing the containment test.
+    pub contained: Vec<String>,
+    /// Higher-coverage transcripts containing this one.
+    pub isoforms: Vec<String>,
+    /// Contained transcripts left after overlap pruning.
+    pub selected: Vec<String>,
+    /// Summed exons of the selected transcripts.
+    pub opg_exons: usize,
+    pub operon: Option<String>,
+    pub decision: Decision,
+}
+
+/// Result of a detection run.
+#[derive(Debug, Default)]
+pub struct Detection {
+    /// (OPRN, container transcript, contained transcript), with OPRNs ordered by container ID
+    /// and their OpGs in transcription order (5'→3').
+    pub operons: Vec<(String, String, String)>,
+    pub operon_ids: HashSet<String>,
+    pub gene_ids: HashSet<String>,
+    pub all_gene_gids: HashSet<String>,
+    pub all_oprn_gids: HashSet<String>,
+    /// OpGs of each OPRN, in transcription order.
+    pub operon_gene_map: HashMap<String, Vec<String>>,
+    pub good_cov_ids: HashSet<String>,
+    pub conflicts: Vec<Conflict>,
+    /// One trace per transcript, in input order by chromosome.
+    pub traces: Vec<ContainerTrace>,
+}
+
+impl Detection {
+    /// Rebuilds a detection from the rows of a previous operons table.
+    pub fn from_operons(operons: Vec<(String, String, String)>, transcripts_by_chrom: &BTreeMap<String, Vec<Transcript>>, monoexonic_t: f32) -> Self {
+        let by_id = gtf::transcripts_by_id(transcripts_by_chrom);
+        let mut detection = Detection::default();
+        for (operon_id, operon, inner_trans) in &operons {
+            match (by_id.get(operon.as_str()), by_id.get(inner_trans.as_str())) {
+                (Some(container), Some(gene)) => {
+                    detection.operon_ids.insert(container.id.clone());
+                    detection.gene_ids.insert(gene.id.clone());
+                    detection.all_oprn_gids.insert(container.gene_id.clone());
+                    detection.all_gene_gids.insert(gene.gene_id.clone());
+                    let genes = detection.operon_gene_map.entry(operon_id.clone()).or_default();
+                    if !genes.contains(&gene.id) {
+                        genes.push(gene.id.clone());
+                    }
+                }
+                _ => log::warn!("{} ({} / {}) not found in the input GTF", operon_id, operon, inner_trans),
+            }
+        }
+        detection.good_cov_ids = by_id
+            .values()
+            .filter(|t| (t.exons.len() > 1 ) || (t.coverage >= monoexonic_t ))
+            .map(|t| t.id.clone())
+            .collect();
+        detection.operons = operons;
+        detection.order_transcriptionally(transcripts_by_chrom);
+        detection
+    }
+}
+
+impl Detection {
+    /// Orders the OpGs of every OPRN 5'→3' on their strand: by position on the plus strand and
+    /// in reverse on the minus strand, keeping the order of the OPRNs.
+    pub fn order_transcriptionally(&mut self, transcripts_by_chrom: &BTreeMap<String, Vec<Transcript>>) {
+        let by_id = gtf::transcripts_by_id(transcripts_by_chrom);
+        for members in self.operon_gene_map.values_mut() {
+            members.sort_by_key(|id| by_id.get(id.as_str()).map(|t| (t.start, t.end)));
+            if members.first().and_then(|id| by_id.get(id.as_str())).is_some_and(|t| t.strand == "Reverse") {
+                members.reverse();
+            }
+        }
+        let operon_order: HashMap<String, usize> = self.operons
+            .iter()
+            .map(|(operon_id, _, _)| operon_id.clone())
+            .unique()
+            .enumerate()
+            .map(|(i, operon_id)| (operon_id, i))
+            .collect();
+        let operon_gene_map = &self.operon_gene_map;
+        self.operons.sort_by_key(|(operon_id, _, inner_trans)| {
+            let rank = operon_gene_map.get(operon_id).and_then(|members| members.iter().position(|id| id == inner_trans));
+            (operon_order[operon_id], rank)
+        });
+    }
+
+    /// Ordinal (1-based, 5'→3') of an OpG in its OPRN and the number of OpGs of the OPRN.
+    pub fn opg_rank(&self, operon_id: &str, opg_id: &str) -> Option<(usize, usize)> {
+        let members = self.operon_gene_map.get(operon_id)?;
+        members.iter().position(|id| id == opg_id).map(|i| (i + 1, members.len()))
+    }
+}
+
+/// Position label of the `rank`-th of `size` genes of an OPRN.
+pub fn position_label(rank: usize, size: usize) -> &'static str {
+    if rank == 1 {
+        "first"
+    } else if rank == size {
+        "last"
+    } else {
+        "internal"
+    }
+}
+
+impl Detection {
+    /// Keeps only the given OPRNs, updating the transcript and gene sets derived from them.
+    /// The containers of the other OPRNs get `reason` as decision, and their claims are dropped.
+    pub fn retain_operons(&mut self, keep: &HashSet<String>, transcripts_by_chrom: &BTreeMap<String, Vec<Transcript>>, reason: Decision) {
+        for trace in self.traces.iter_mut().filter(|t| t.decision == Decision::Operon) {
+            if trace.operon.as_ref().is_some_and(|operon_id| !keep.contains(operon_id)) {
+                trace.decision = reason;
+            }
+        }
+        for conflict in self.conflicts.iter_mut().filter(|c| !keep.contains(&c.operon)) {
+            conflict.kept = false;
+        }
+        let gene_of: HashMap<&str, &str> = transcripts_by_chrom.values().flatten().map(|t| (t.id.as_str(), t.gene_id.as_str())).collect();
+        self.operons.retain(|(operon_id, _, _)| keep.contains(operon_id));
+        self.operon_gene_map.retain(|operon_id, _| keep.contains(operon_id));
+        self.operon_ids = self.operons.iter().map(|(_, operon, _)| operon.clone()).collect();
+        self.gene_ids = self.operons.iter().map(|(_, _, inner_trans)| inner_trans.clone()).collect();
+        self.all_oprn_gids = self.operon_ids.iter().filter_map(|id| gene_of.get(id.as_str())).map(|g| g.to_string()).collect();
+        self.all_gene_gids = self.gene_ids.iter().filter_map(|id| gene_of.get(id.as_str())).map(|g| g.to_string()).collect();
+    }
+}
+
+/// Reads an operons table: the first column is the OPRN and the last one the contained
+/// transcript; with three or more columns the second one is the container. In a gamba
+/// table the contained transcript is the 'Contained_transcript' column.
+pub fn read_operons(path: &Path) -> anyhow::Result<Vec<(String, String, String)>> {
+    let mut operons = Vec::new();
+    let mut member_column = None;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.starts_with("Operon\t") {
+            member_column = line.split('\t').position(|c| c == "Contained_transcript");
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 2 {
+            anyhow::bail!("{}: expected at least two columns in line '{}'", path.display(), line);
+        }
+        let container = if fields.len() >= 3 { fields[1] } else { "." };
+        let member = member_column.and_then(|j| fields.get(j)).unwrap_or(&fields[fields.len() - 1]);
+        operons.push((fields[0].to_string(), container.to_string(), member.to_string()));
+    }
+    Ok(operons)
+}
+
+pub fn exons_overlap(t1: &Transcript, t2: &Transcript, min_overlap: f32, bp_overlap: u64) -> bool {
+    let mut count_overlap = 0 ;
+    let mut bp_overlap = bp_overlap ;
+    for (s1, e1) in &t1.exons {
+        for (s2, e2) in &t2.exons {
+            if e1.saturating_sub(*s1) < bp_overlap || e2.saturating_sub(*s2) < bp_overlap {
+                bp_overlap = std::cmp::min(e2.saturating_sub(*s2), e1.saturating_sub(*s1))
+            }
+            let overlap_start = std::cmp::max(*s1, *s2);
+            let overlap_end = std::cmp::min(*e1, *e2);
+            if overlap_end > overlap_start && overlap_end.saturating_sub(overlap_start) >= bp_overlap {
+                count_overlap +=1 ;
+            }
+        }
+    }
+    let required_overlaps = (t2.exons.len() as f32 * min_overlap).ceil() as usize;
+    if count_overlap >= required_overlaps {
+        return true ;
+    }
+    false
+}
+
+// Helper to resolve if t1 (OPRN) contains t2 (OpG); t1 less cov than t2
+pub fn transcripts_inside_op(t1: &Transcript, t2: &Transcript, tolerance: u64, threshold: f32, monoexonic_t: f32, min_overlap: f32, bp_overlap: u64) -> bool {
+    t1.start <= t2.start + tolerance && t2.start + tolerance < t1.end + tolerance 
+    && t1.end + tolerance >= t2.end && t2.end > t1.start
+    && t1.coverage * threshold < t2.coverage
+    && (t2.exons.len() > 1 || (t1.coverage * monoexonic_t < t2.coverage))
+    && exons_overlap(t1, t2, min_overlap, bp_overlap)
+}
+// Helper to resolve if t2 is an isoform of t1; t2 less cov than t1
+pub fn transcripts_inside(t1: &Transcript, t2: &Transcript, tolerance: u64, threshold: f32, monoexonic_t: f32,) -> bool {
+    t1.start <= t2.start + tolerance && t2.start + tolerance < t1.end + tolerance 
+    && t1.end + tolerance >= t2.end && t2.end > t1.start
+    && t1.coverage > t2.coverage * threshold
+    && (t2.exons.len() > 1 || (t1.coverage > t2.coverage * monoexonic_t ))
+}
+
+pub fn transcripts_no_overlap(t1: &Transcript, t2: &Transcript, tolerance: u64) -> bool {
+    t1.start > t2.end.saturating_sub(tolerance)
+}
+
+// Weighted interval scheduling: the non-overlapping subset of candidates with the
+// highest summed objective (ties broken by number of transcripts), sorted by start.
+pub fn select_optimal<'a>(candidates: &[&'a Transcript], objective: Objective, tolerance: u64) -> Vec<&'a Transcript> {
+    let mut sorted: Vec<&Transcript> = candidates.iter().copied().unique_by(|t| &t.id).collect();
+    sorted.sort_by(|t1, t2| t1.end.cmp(&t2.end).then(t1.start.cmp(&t2.start)).then(t1.id.cmp(&t2.id)));
+
+    // best[j] = (objective, count) using the first j candidates; take[j] records if candidate j-1 is used
+    let mut best: Vec<(f64, usize)> = vec![(0.0, 0)];
+    let mut take = vec![false];
+    let mut previous = vec![0];
+    for (j, gene) in sorted.iter().enumerate() {
+        let p = sorted[..j].partition_point(|last| transcripts_no_overlap(gene, last, tolerance));
+        let with = (best[p].0 + objective.weight(gene), best[p].1 + 1);
+        let without = best[j];
+        let use_gene = with.0 > without.0 || (with.0 == without.0 && with.1 > without.1);
+        best.push(if use_gene { with } else { without });
+        take.push(use_gene);
+        previous.push(p);
+    }
+
+    let mut selected = Vec::new();
+    let mut j = sorted.len();
+    while j > 0 {
+        if take[j] {
+            selected.push(sorted[j - 1]);
+            j = previous[j];
+        } else {
+            j -= 1;
+        }
+    }
+    selected.sort_by_key(|t| t.start);
+    selected
+}
+
+pub fn operontrans_overlap(t1: &Transcript, t2: &Transcript, tolerance: u64) -> bool {
+    t1.start <= t2.end.saturating_sub(tolerance) && t1.end >= t2.start + tolerance
+}
+
+pub fn detect_operons(transcripts_by_chrom: &BTreeMap<String, Vec<Transcript>>, params: &Params) -> Detection {
+    let Params { threshold, monoexonic_t, min_overlap, bp_overlap: min_bp_overlap, tolerance, opg_tolerance, selection, objective, .. } = *params;
+
+    let mut operon_to_genes: Vec<(GeneId, Transcript, &Transcript)> = Vec::new();
+    let mut good_cov_ids = HashSet::new();
+    let mut traces = Vec::new();
+    for (chrom, transcripts) in transcripts_by_chrom {
+        log::info!("Processing chromosome {} ({} transcripts)...", chrom, transcripts.len());
+        for container in transcripts {
+            let mut contained = Vec::new();
+            let mut counter=0;
+            let mut isoforms = Vec::new();
+            if (container.exons.len() > 1 ) || (container.coverage >= monoexonic_t ){
+                good_cov_ids.insert(container.id.clone());
+            }
+            for inner in transcripts {
+                if container.id == inner.id || container.strand != inner.strand {
+                    continue;
+                }
+                if transcripts_inside_op(container,inner, tolerance, threshold, monoexonic_t , min_overlap, min_bp_overlap) {
+                    contained.push(inner);
+                }
+                if transcripts_inside(inner,container, tolerance, threshold, monoexonic_t) {
+                    counter += 1;
+                    isoforms.push(inner.id.clone());
+                }
+            }
+            let mut trace = ContainerTrace {
+                container_id: container.id.clone(),
+                chrom: container.chrom.clone(),
+                strand: container.strand.clone(),
+                start: container.start,
+                end: container.end,
+                coverage: container.coverage,
+                container_exons: container.exons.len(),
+                contained: contained.iter().map(|t| t.id.clone()).collect(),
+                isoforms,
+                selected: Vec::new(),
+                opg_exons: 0,
+                operon: None,
+                decision: match contained.len() {
+                    0 => Decision::NoContained,
+                    1 => Decision::FewContained,
+                    _ => Decision::HigherCovIsoform,
+                },
+            };
+            if contained.len() >= 2 && counter == 0 {
+                let non_overlapping = match selection {
+                    Selection::Optimal => select_optimal(&contained, objective, opg_tolerance),
+                    Selection::Greedy => {
+                        let mut non_overlapping = Vec::new();
+                        contained.sort_by(|&e1 , &e2| {
+                            let id1 = e1.start;
+                            let id2 = e2.start;
+                            if id1 > id2 { Ordering::Greater } else if id1 < id2 { Ordering::Less } else { Ordering::Equal }
+                        });
+                        for gene in contained {
+                            if non_overlapping.last().is_none_or(|last: &&Transcript| transcripts_no_overlap(gene,last,opg_tolerance) ) {
+                                non_overlapping.push(gene);
+                            } else {
+                                let last = *non_overlapping.last().unwrap();
+                                if gene.exons.len() > 1
+                                    && (last.exons.len() == 1 || gene.fpkm_val > last.fpkm_val || (gene.fpkm_val == last.fpkm_val && gene.exons.len() > last.exons.len())) {
+                                    non_overlapping.pop();
+                                    non_overlapping.push(gene);
+                                }
+                                if gene.exons.len() == 1 && last.exons.len() == 1 && gene.fpkm_val > last.fpkm_val {
+                                    non_overlapping.pop();
+                                    non_overlapping.push(gene);
+                                }
+                            }
+                        }
+                        non_overlapping
+                    }
+                };
+
+                trace.selected = non_overlapping.iter().map(|t| t.id.clone()).collect();
+                trace.decision = Decision::OverlapPruning;
+                if non_overlapping.len() >= 2 {
+                    let mut exon_num=0;
+                    for gene in non_overlapping.clone() { exon_num += gene.exons.len() }
+                    trace.opg_exons = exon_num;
+                    trace.decision = Decision::ExonGain;
+                    if exon_num > container.exons.len() {
+                        trace.decision = Decision::Claimed;
+                        for gene in non_overlapping {
+                            operon_to_genes.push((container.gene_id.clone(), container.clone(), gene));
+                        }
+                    }
+                }
+            }
+            traces.push(trace);
+        }
+    }
+    let mut detection = resolve_claims(operon_to_genes, good_cov_ids, traces, params);
+    detection.order_transcriptionally(transcripts_by_chrom);
+    detection
+}
+
+/// Second stage of the detection, shared by every mode: resolves the OpGs claimed by several
+/// containers, clusters the containers into OPRNs and selects their non-overlapping OpGs.
+pub fn resolve_claims(operon_to_genes: Vec<(GeneId, Transcript, &Transcript)>, good_cov_ids: HashSet<String>, mut traces: Vec<ContainerTrace>, params: &Params) -> Detection {
+    let Params { tolerance, opg_tolerance, conflict_policy, selection, objective, .. } = *params;
+
+    let mut chr_to_operons: BTreeMap<(String, String), Vec<OperonClaim>> = BTreeMap::new();
+    for (op_gene_id, op_id, trans_id) in operon_to_genes.iter() {
+        chr_to_operons.entry((op_id.chrom.clone(),op_id.strand.clone())).or_default().push((op_gene_id.clone(), op_id, *trans_id));
+    }
+
+    // Containers claiming each inner transcript, used to detect and resolve conflicts
+    let mut claims_by_inner: BTreeMap<String, Vec<&Transcript>> = BTreeMap::new();
+    for (_, container, inner_trans) in operon_to_genes.iter() {
+        claims_by_inner.entry(inner_trans.id.clone()).or_default().push(container);
+    }
+    let preferred_container: HashMap<String, String> = claims_by_inner
+        .iter()
+        .filter_map(|(inner_id, containers)| {
+            let best = match conflict_policy {
+                ConflictPolicy::Coverage => containers.iter().max_by(|c1, c2| {
+                    c1.coverage.total_cmp(&c2.coverage).then(c2.start.cmp(&c1.start))
+                }),
+                ConflictPolicy::Longest => containers.iter().max_by(|c1, c2| {
+                    (c1.end - c1.start).cmp(&(c2.end - c2.start)).then(c2.start.cmp(&c1.start))
+                }),
+                ConflictPolicy::First | ConflictPolicy::KeepAll => None,
+            }?;
+            Some((inner_id.clone(), best.id.clone()))
+        })
+        .collect();
+
+    let mut overlapping: Vec<(String, Transcript, &Transcript)> = Vec::new();
+    let mut seen_transcripts = HashSet::new();
+    let mut counter = 1;
+    for ((_chrom, _strand), mut op_list) in chr_to_operons {
+        op_list.sort_by_key(|(_, p, _)| p.start);
+        for (_ , current_op, inner_trans) in op_list {
+            let skip = match conflict_policy {
+                ConflictPolicy::First => seen_transcripts.contains(&inner_trans.id),
+                ConflictPolicy::Coverage | ConflictPolicy::Longest => preferred_container.get(&inner_trans.id) != Some(&current_op.id),
+                ConflictPolicy::KeepAll => false,
+            };
+            if skip {
+                continue;
+            }
+            if let Some((_, last, _ ) ) = overlapping.last() {
+                if operontrans_overlap(current_op, last, tolerance) {
+                    overlapping.push((format!("OPRN.{}", counter), current_op.clone(), inner_trans));
+                } else {
+                    counter += 1;
+                    overlapping.push((format!("OPRN.{}", counter), current_op.clone(), inner_trans));
+                }
+            } else {
+                overlapping.push((format!("OPRN.{}", counter), current_op.clone(), inner_trans));
+            }
+            seen_transcripts.insert(inner_trans.id.clone());
+        }
+    }
+
+    let mut operon_to_trans: HashMap<String, Vec<(Transcript,&Transcript)>> = HashMap::new();
+    for (op_id, operon, inner_trans) in overlapping.iter() {
+        operon_to_trans.entry(op_id.clone()).or_default().push((operon.clone(), inner_trans));
+    }
+    let mut operon_to_trans_def: Vec<(String, String, String)> = Vec::new();
+    let mut operon_ids = HashSet::new();
+    let mut gene_ids = HashSet::new();
+    let mut all_gene_gids = HashSet::new();
+    let mut all_oprn_gids = HashSet::new();
+    let mut operon_gene_map: HashMap<String, Vec<String>> = HashMap::new();
+
+    for (operon_id, transcripts_list) in operon_to_trans {
+        let non_overlapping_def: Vec<&Transcript> = match selection {
+            Selection::Optimal => {
+                let candidates: Vec<&Transcript> = transcripts_list.iter().map(|(_, gene)| *gene).collect();
+                select_optimal(&candidates, objective, opg_tolerance)
+            }
+            Selection::Greedy => {
+                let mut non_overlapping_def: Vec<&Transcript> = Vec::new();
+                let transcripts_list_ordered = transcripts_list.iter().sorted_by(|(_, e1 ), (_, e2)| {
+                    let id1 = e1.start;
+                    let id2 = e2.start;
+                    let id1_strand = &e1.strand;
+                    let id2_strand = &e2.strand;
+                    if id1_strand == id2_strand && id1 > id2 { Ordering::Greater } else if id1_strand == id2_strand && id1 < id2 { Ordering::Less } else { Ordering::Equal }
+                });
+                for (_, gene) in transcripts_list_ordered {
+                    if non_overlapping_def.last().is_none_or(|last: &&Transcript| transcripts_no_overlap(gene,last,opg_tolerance) ) {
+                        non_overlapping_def.push(gene);
+                    } else if gene.fpkm_val > non_overlapping_def.last().unwrap().fpkm_val {
+                        non_overlapping_def.pop();
+                        non_overlapping_def.push(gene);
+                    }
+                }
+                non_overlapping_def
+            }
+        };
+
+        if non_overlapping_def.len() >= 2 {
+            for (operon, gene) in transcripts_list {
+                if non_overlapping_def.iter().any(|&i| i.id == gene.id ) {
+                    operon_to_trans_def.push((operon_id.clone(), operon.id.clone(), gene.id.clone()));
+                    operon_ids.insert(operon.id.clone());
+                    gene_ids.insert(gene.id.clone());
+                    all_gene_gids.insert(gene.gene_id.clone());
+                    all_oprn_gids.insert(operon.gene_id.clone());
+                    let genes = operon_gene_map.entry(operon_id.clone()).or_default();
+                    if !genes.contains(&gene.id) {
+                        genes.push(gene.id.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    // StringTie IDs (MSTRG.N.M) by their numbers, then any other ID by name
+    let stringtie_number = |id: &str| {
+        let els = id.split(".").collect::<Vec<_>>();
+        if els.len() < 3 { return None }
+        format!("{}.{}", els[1], els[2]).parse::<f32>().ok()
+    };
+    operon_to_trans_def.sort_by(|(_,e1,_) , (_, e2, _)| {
+            match (stringtie_number(e1), stringtie_number(e2)) {
+                (Some(id1), Some(id2)) => if id1 > id2 { Ordering::Greater } else if id1 < id2 { Ordering::Less } else { Ordering::Equal },
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => e1.cmp(e2),
+            }
+        });
+
+    // Every OpG claimed by more than one container, with the OPRN of each claim; a claim is
+    // kept only if it survived the OPRN-level non-overlap selection
+    let mut oprn_by_claim: HashMap<(&str, &str), &str> = HashMap::new();
+    let mut oprn_by_container: HashMap<&str, &str> = HashMap::new();
+    for (op_id, operon, inner_trans) in overlapping.iter() {
+        oprn_by_claim.insert((operon.id.as_str(), inner_trans.id.as_str()), op_id.as_str());
+        oprn_by_container.entry(operon.id.as_str()).or_insert(op_id.as_str());
+    }
+    let final_claims: HashSet<(&str, &str)> = operon_to_trans_def
+        .iter()
+        .map(|(_, operon, inner_trans)| (operon.as_str(), inner_trans.as_str()))
+        .collect();
+    let mut conflicts = Vec::new();
+    for (inner_id, containers) in claims_by_inner.iter().filter(|(_, containers)| containers.len() > 1) {
+        for container in containers {
+            let claim = (container.id.as_str(), inner_id.as_str());
+            let operon = oprn_by_claim.get(&claim).or(oprn_by_container.get(container.id.as_str())).unwrap_or(&"NA");
+            conflicts.push(Conflict {
+                inner_id: inner_id.clone(),
+                container_id: container.id.clone(),
+                operon: operon.to_string(),
+                container_cov: container.coverage,
+                container_length: container.end - container.start + 1,
+                kept: final_claims.contains(&claim),
+            });
+        }
+    }
+
+    // Resolve the containers that reached the clustering stage
+    let final_oprn_by_container: HashMap<&str, &str> = operon_to_trans_def
+        .iter()
+        .map(|(op_id, operon, _)| (operon.as_str(), op_id.as_str()))
+        .collect();
+    for trace in traces.iter_mut().filter(|t| t.decision == Decision::Claimed) {
+        if let Some(op_id) = final_oprn_by_container.get(trace.container_id.as_str()) {
+            trace.operon = Some(op_id.to_string());
+            trace.decision = Decision::Operon;
+        } else if let Some(op_id) = oprn_by_container.get(trace.container_id.as_str()) {
+            trace.operon = Some(op_id.to_string());
+            trace.decision = Decision::OprnPruning;
+        } else {
+            trace.decision = Decision::ClaimsLost;
+        }
+    }
+
+    Detection {
+        operons: operon_to_trans_def,
+        operon_ids,
+        gene_ids,
+        all_gene_gids,
+        all_oprn_gids,
+        operon_gene_map,
+        good_cov_ids,
+        conflicts,
+        traces,
+    }
+}
+
+/// Number of OPRNs by number of OpGs.
+pub fn size_summary(operon_gene_map: &HashMap<String, Vec<String>>) -> BTreeMap<&'static str, usize> {
+    let mut summary = BTreeMap::from([
+        //("1 gene", 0),
+        ("2 genes", 0),
+        ("3 genes", 0),
+        ("4 genes", 0),
+        ("5 genes", 0),
+        (">5 genes", 0),
+    ]);
+
+    for genes in operon_gene_map.values() {
+        match genes.len() {
+            //1 => *summary.get_mut("1 genes").unwrap() += 1,
+            2 => *summary.get_mut("2 genes").unwrap() += 1,
+            3 => *summary.get_mut("3 genes").unwrap() += 1,
+            4 => *summary.get_mut("4 genes").unwrap() += 1,
+            5 => *summary.get_mut("5 genes").unwrap() += 1,
+            n if n > 5 => *summary.get_mut(">5 genes").unwrap() += 1,
+            _ => {},
+        }
+    }
+    summary
+}
+
+/// A gene of an OPRN, with every isoform of the gene present in the input.
+#[derive(Debug, Clone)]
+pub struct OperonGene {
+    pub operon_id: String,
+    pub gene_id: String,
+    pub chrom: String,
+    /// Span of the OpG and its isoforms.
+    pub start: u64,
+    pub end: u64,
+    pub strand: String,
+    pub isoforms: Vec<String>,
+    /// Coverage and FPKM summed over the isoforms.
+    pub coverage: f32,
+    pub fpkm: f32,
+}
+
+/// Gene of every OpG of each OPRN, with OPRNs in the order of the operons table and OpGs in
+/// transcription order (5'→3'). The isoforms of an OpG are the transcripts of its gene_id that
+/// overlap it, leaving out the containers and the other OpGs of the OPRN: StringTie often gives
+/// a container and its OpGs the same gene_id.
+pub fn operon_genes(detection: &Detection, transcripts_by_chrom: &BTreeMap<String, Vec<Transcript>>) -> Vec<OperonGene> {
+    let mut by_id: HashMap<&str, &Transcript> = HashMap::new();
+    let mut isoforms_by_gene: HashMap<&str, Vec<&Transcript>> = HashMap::new();
+    for transcript in transcripts_by_chrom.values().flatten() {
+        by_id.insert(transcript.id.as_str(), transcript);
+        if !detection.operon_ids.contains(&transcript.id) {
+            isoforms_by_gene.entry(transcript.gene_id.as_str()).or_default().push(transcript);
+        }
+    }
+
+    let mut genes = Vec::new();
+    for operon_id in detection.operons.iter().map(|(operon_id, _, _)| operon_id).unique() {
+        let Some(members) = detection.operon_gene_map.get(operon_id) else { continue };
+        for opg in members.iter().filter_map(|id| by_id.get(id.as_str())) {
+            let isoforms: Vec<&Transcript> = isoforms_by_gene[opg.gene_id.as_str()]
+                .iter()
+                .filter(|t| t.id == opg.id || (!members.contains(&t.id) && t.start <= opg.end && t.end >= opg.start))
+                .copied()
+                .collect();
+            genes.push(OperonGene {
+                operon_id: operon_id.clone(),
+                gene_id: opg.gene_id.clone(),
+                chrom: opg.chrom.clone(),
+                start: isoforms.iter().map(|t| t.start).min().unwrap_or(0),
+                end: isoforms.iter().map(|t| t.end).max().unwrap_or(0),
+                strand: opg.strand.clone(),
+                isoforms: isoforms.iter().map(|t| t.id.clone()).sorted().collect(),
+                coverage: isoforms.iter().map(|t| t.coverage).sum(),
+                fpkm: isoforms.iter().map(|t| t.fpkm_val).sum(),
+            });
+        }
+    }
+    genes
+}
+
+/// A whole OPRN, as written in the operon feature outputs.
+#[derive(Debug, Clone)]
+pub struct OperonFeature {
+    pub operon_id: String,
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+    pub strand: String,
+    /// Gene IDs, in transcription order.
+    pub genes: Vec<String>,
+    pub containers: Vec<String>,
+    /// Number of OpGs.
+    pub size: usize,
+    /// Mean coverage of the OpGs.
+    pub score: f32,
+}
+
+/// One feature per OPRN, in the order of the operons table.
+pub fn operon_features(detection: &Detection, genes: &[OperonGene], transcripts_by_chrom: &BTreeMap<String, Vec<Transcript>>, span: OperonSpan) -> Vec<OperonFeature> {
+    let by_id = gtf::transcripts_by_id(transcripts_by_chrom);
+    let mut features = Vec::new();
+    for operon_id in detection.operons.iter().map(|(operon_id, _, _)| operon_id).unique() {
+        let containers: Vec<&Transcript> = detection.operons
+            .iter()
+            .filter(|(id, _, _)| id == operon_id)
+            .filter_map(|(_, container, _)| by_id.get(container.as_str()).copied())
+            .unique_by(|t| t.id.as_str())
+            .collect();
+        let opgs: Vec<&Transcript> = detection.operon_gene_map
+            .get(operon_id)
+            .map(|members| members.iter().filter_map(|id| by_id.get(id.as_str()).copied()).collect())
+            .unwrap_or_default();
+        let Some(first) = opgs.first() else { continue };
+        let spanned = match span {
+            OperonSpan::Opgs => &opgs,
+            OperonSpan::Containers if containers.is_empty() => &opgs,
+            OperonSpan::Containers => &containers,
+        };
+        features.push(OperonFeature {
+            operon_id: operon_id.clone(),
+            chrom: first.chrom.clone(),
+            start: spanned.iter().map(|t| t.start).min().unwrap_or(0),
+            end: spanned.iter().map(|t| t.end).max().unwrap_or(0),
+            strand: first.strand.clone(),
+            genes: genes.iter().filter(|g| &g.operon_id == operon_id).map(|g| g.gene_id.clone()).collect(),
+            containers: containers.iter().map(|t| t.id.clone()).collect(),
+            size: opgs.len(),
+            score: opgs.iter().map(|t| t.coverage).sum::<f32>() / opgs.len() as f32,
+        });
+    }
+    features
+}
+
+/// Region between two consecutive OpGs of an OPRN, numbered 5'→3'.
+#[derive(Debug, Clone)]
+pub struct Intercistronic {
+    pub operon_id: String,
+    pub rank: usize,
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+    pub strand: String,
+    /// Gene IDs of the flanking OpGs, in transcription order.
+    pub upstream: String,
+    pub downstream: String,
+}
+
+/// OpGs of every OPRN in transcription order (5'→3'), with OPRNs in the order of the operons table.
+pub fn opgs_by_operon<'a>(detection: &Detection, transcripts_by_chrom: &'a BTreeMap<String, Vec<Transcript>>) -> Vec<(String, Vec<&'a Transcript>)> {
+    let by_id = gtf::transcripts_by_id(transcripts_by_chrom);
+    let mut operons = Vec::new();
+    for operon_id in detection.operons.iter().map(|(operon_id, _, _)| operon_id).unique() {
+        let Some(members) = detection.operon_gene_map.get(operon_id) else { continue };
+        let opgs: Vec<&Transcript> = members.iter().filter_map(|id| by_id.get(id.as_str()).copied()).collect();
+        operons.push((operon_id.clone(), opgs));
+    }
+    operons
+}
+
+/// Intercistronic regions of every OPRN; OpGs that overlap or abut leave no region.
+pub fn intercistronic_regions(detection: &Detection, transcripts_by_chrom: &BTreeMap<String, Vec<Transcript>>) -> Vec<Intercistronic> {
+    let mut regions = Vec::new();
+    for (operon_id, opgs) in opgs_by_operon(detection, transcripts_by_chrom) {
+        let operon_regions = opgs
+            .windows(2)
+            .map(|pair| {
+                let (upstream, downstream) = (pair[0], pair[1]);
+                let (left, right) = if upstream.start <= downstream.start { (upstream, downstream) } else { (downstream, upstream) };
+                (upstream, downstream, left.end + 1, right.start)
+            })
+            .filter(|(_, _, start, right_start)| right_start > start)
+            .enumerate()
+            .map(|(i, (upstream, downstream, start, right_start))| Intercistronic {
+                operon_id: operon_id.clone(),
+                rank: i + 1,
+                chrom: upstream.chrom.clone(),
+                start,
+                end: right_start - 1,
+                strand: upstream.strand.clone(),
+                upstream: upstream.gene_id.clone(),
+                downstream: downstream.gene_id.clone(),
+            });
+        regions.extend(operon_regions);
+    }
+    regions
+}
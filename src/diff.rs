@@ -0,0 +1,271 @@
+// diff.rs
+use std::{collections::{BTreeMap, HashMap}, io::Write, path::PathBuf};
+use itertools::Itertools;
+use crate::detect::{self, Measure};
+use crate::gtf;
+use crate::output::{OutputArgs, OutputPaths, Provenance};
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    /// Operons table ('_operons_found_' TSV) defining the containers and OpGs of every OPRN.
+    #[arg(long)]
+    operons: PathBuf,
+
+    /// Sample GTF with its condition, as CONDITION=FILE (can be repeated; StringTie '-e' outputs
+    /// of every sample on the same transcripts).
+    #[arg(long = "sample", value_name = "CONDITION=FILE", value_parser = parse_sample, required = true)]
+    samples: Vec<(String, PathBuf)>,
+
+    /// Conditions to compare, as REFERENCE,CONDITION [default: the first two conditions given].
+    #[arg(long, value_delimiter = ',', num_args = 2)]
+    contrast: Vec<String>,
+
+    /// Expression value used for the ratios.
+    #[arg(long, value_enum, default_value_t = Measure::Fpkm)]
+    measure: Measure,
+
+    /// Log and skip malformed GTF records instead of stopping at the first one.
+    #[arg(long)]
+    skip_invalid: bool,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+// Pseudocount added to both sides of the ratios
+const PSEUDOCOUNT: f64 = 0.01;
+
+fn parse_sample(s: &str) -> Result<(String, PathBuf), String> {
+    match s.split_once('=') {
+        Some((condition, path)) if !condition.is_empty() && !path.is_empty() => Ok((condition.to_string(), PathBuf::from(path))),
+        _ => Err(format!("'{}' is not of the form CONDITION=FILE", s)),
+    }
+}
+
+// ln Γ(x), Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    const COF: [f64; 6] = [76.18009172947146, -86.50532032941677, 24.01409824083091, -1.231739572450155, 0.1208650973866179e-2, -0.5395239384953e-5];
+    let mut y = x;
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut ser = 1.000000000190015;
+    for c in COF {
+        y += 1.0;
+        ser += c / y;
+    }
+    -tmp + (2.5066282746310005 * ser / x).ln()
+}
+
+// Continued fraction of the incomplete beta function
+fn beta_cf(a: f64, b: f64, x: f64) -> f64 {
+    const FPMIN: f64 = 1e-300;
+    let (qab, qap, qam) = (a + b, a + 1.0, a - 1.0);
+    let mut c = 1.0;
+    let mut d = 1.0 - qab * x / qap;
+    if d.abs() < FPMIN {
+        d = FPMIN;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..=200 {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+        let aa = m * (b - m) * x / ((qam + m2) * (a + m2));
+        d = 1.0 + aa * d;
+        if d.abs() < FPMIN {
+            d = FPMIN;
+        }
+        c = 1.0 + aa / c;
+        if c.abs() < FPMIN {
+            c = FPMIN;
+        }
+        d = 1.0 / d;
+        h *= d * c;
+        let aa = -(a + m) * (qab + m) * x / ((a + m2) * (qap + m2));
+        d = 1.0 + aa * d;
+        if d.abs() < FPMIN {
+            d = FPMIN;
+        }
+        c = 1.0 + aa / c;
+        if c.abs() < FPMIN {
+            c = FPMIN;
+        }
+        d = 1.0 / d;
+        let del = d * c;
+        h *= del;
+        if (del - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    h
+}
+
+// Regularized incomplete beta function I_x(a, b)
+fn beta_inc(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let bt = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    if x < (a + 1.0) / (a + b + 2.0) {
+        bt * beta_cf(a, b, x) / a
+    } else {
+        1.0 - bt * beta_cf(b, a, 1.0 - x) / b
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Welch's two-sample t-test: (t, two-sided p-value), or `None` with fewer than two
+/// replicates per group or no variance.
+fn welch_test(a: &[f64], b: &[f64]) -> Option<(f64, f64)> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+    let variance = |v: &[f64]| {
+        let m = mean(v);
+        v.iter().map(|x| (x - m).powi(2)).sum::<f64>() / (v.len() - 1) as f64
+    };
+    let (va, vb) = (variance(a) / a.len() as f64, variance(b) / b.len() as f64);
+    if va + vb == 0.0 {
+        return None;
+    }
+    let t = (mean(b) - mean(a)) / (va + vb).sqrt();
+    let df = (va + vb).powi(2) / (va.powi(2) / (a.len() - 1) as f64 + vb.powi(2) / (b.len() - 1) as f64);
+    Some((t, beta_inc(df / 2.0, 0.5, df / (df + t * t))))
+}
+
+/// Benjamini-Hochberg adjusted p-values; `None` values are left out.
+fn adjust_bh(pvalues: &[Option<f64>]) -> Vec<Option<f64>> {
+    let mut order: Vec<usize> = (0..pvalues.len()).filter(|&i| pvalues[i].is_some()).collect();
+    order.sort_by(|&i, &j| pvalues[j].partial_cmp(&pvalues[i]).unwrap());
+    let n = order.len() as f64;
+    let mut adjusted = vec![None; pvalues.len()];
+    let mut running_min: f64 = 1.0;
+    for (k, &i) in order.iter().enumerate() {
+        let rank = n - k as f64;
+        running_min = running_min.min(pvalues[i].unwrap() * n / rank);
+        adjusted[i] = Some(running_min);
+    }
+    adjusted
+}
+
+fn format_optional(value: Option<f64>) -> String {
+    value.map(|v| format!("{:.4e}", v)).unwrap_or_else(|| "NA".into())
+}
+
+/// Compares the container/OpG expression ratio of every OPRN between two conditions.
+pub fn run(args: DiffArgs) -> anyhow::Result<()> {
+    let mut out = OutputPaths::new(&args.operons, &args.output)?;
+    out.init_log()?;
+    let mut conditions: Vec<&str> = args.samples.iter().map(|(condition, _)| condition.as_str()).unique().collect();
+    if !args.contrast.is_empty() {
+        conditions = args.contrast.iter().map(|c| c.as_str()).collect();
+    }
+    if conditions.len() < 2 {
+        anyhow::bail!("at least two conditions are needed, got {}", conditions.len());
+    }
+    let (reference, condition) = (conditions[0], conditions[1]);
+    for c in [reference, condition] {
+        if !args.samples.iter().any(|(sample_condition, _)| sample_condition == c) {
+            anyhow::bail!("no sample for condition '{}'", c);
+        }
+    }
+    let mut provenance_params = vec![
+        ("contrast", format!("{},{}", reference, condition)),
+        ("measure", format!("{:?}", args.measure)),
+    ];
+    provenance_params.extend(args.samples.iter().map(|(c, path)| ("sample", format!("{}={}", c, path.display()))));
+    out.provenance = Some(Provenance::new(&args.operons, &provenance_params)?);
+
+    // Containers and OpGs of each OPRN
+    let rows = detect::read_operons(&args.operons)?;
+    let mut operons: BTreeMap<&str, (Vec<&str>, Vec<&str>)> = BTreeMap::new();
+    for (operon_id, container, member) in &rows {
+        let (containers, opgs) = operons.entry(operon_id.as_str()).or_default();
+        if container != "." && !containers.contains(&container.as_str()) {
+            containers.push(container);
+        }
+        if !opgs.contains(&member.as_str()) {
+            opgs.push(member);
+        }
+    }
+    let operon_order: Vec<&str> = rows.iter().map(|(operon_id, _, _)| operon_id.as_str()).unique().collect();
+
+    // log2((container + c) / (mean OpG + c)) of each OPRN in each sample of the contrast
+    let ratios_path = format!("{}_diff_ratios.tsv", out.prefix);
+    let mut ratios_file = out.create(ratios_path.clone())?;
+    writeln!(ratios_file, "Operon\tSample\tCondition\tContainer_expression\tOpG_expression\tLog2_ratio")?;
+    let mut log_ratios: HashMap<(&str, &str), Vec<f64>> = HashMap::new();
+    for (sample_condition, path) in args.samples.iter().filter(|(c, _)| c == reference || c == condition) {
+        let annotation = gtf::read_annotation(path, args.skip_invalid)?;
+        let by_id = annotation.by_id();
+        let mut missing = 0;
+        let mut expression = |id: &str| match by_id.get(id) {
+            Some(t) => args.measure.value(t) as f64,
+            None => {
+                missing += 1;
+                0.0
+            }
+        };
+        let sample = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        for operon_id in &operon_order {
+            let (containers, opgs) = &operons[operon_id];
+            let container_expression: f64 = containers.iter().map(|id| expression(id)).sum();
+            let opg_expression = opgs.iter().map(|id| expression(id)).sum::<f64>() / opgs.len() as f64;
+            let log_ratio = ((container_expression + PSEUDOCOUNT) / (opg_expression + PSEUDOCOUNT)).log2();
+            log_ratios.entry((operon_id, sample_condition.as_str())).or_default().push(log_ratio);
+            writeln!(
+                ratios_file,
+                "{}\t{}\t{}\t{:.4}\t{:.4}\t{:.4}",
+                operon_id, sample, sample_condition, container_expression, opg_expression, log_ratio
+            )?;
+        }
+        if missing > 0 {
+            log::warn!("{}: {} containers/OpGs of the operons table not found (expression 0)", path.display(), missing);
+        }
+        log::info!("Read {} ({})", path.display(), sample_condition);
+    }
+
+    let tests: Vec<_> = operon_order
+        .iter()
+        .map(|operon_id| {
+            let a = &log_ratios[&(*operon_id, reference)];
+            let b = &log_ratios[&(*operon_id, condition)];
+            (mean(a), mean(b), mean(b) - mean(a), welch_test(a, b))
+        })
+        .collect();
+    let adjusted = adjust_bh(&tests.iter().map(|(_, _, _, test)| test.map(|(_, p)| p)).collect::<Vec<_>>());
+
+    let diff_path = format!("{}_diff_usage.tsv", out.prefix);
+    let mut diff_file = out.create(diff_path.clone())?;
+    writeln!(
+        diff_file,
+        "Operon\tContainers\tOpGs\tLog2_ratio_{}\tLog2_ratio_{}\tLog2FC\tT\tP_value\tAdj_p_value",
+        reference, condition
+    )?;
+    for (i, operon_id) in operon_order.iter().enumerate() {
+        let (containers, opgs) = &operons[operon_id];
+        let (mean_a, mean_b, log2fc, test) = tests[i];
+        writeln!(
+            diff_file,
+            "{}\t{}\t{}\t{:.4}\t{:.4}\t{:.4}\t{}\t{}\t{}",
+            operon_id,
+            containers.join(","),
+            opgs.len(),
+            mean_a,
+            mean_b,
+            log2fc,
+            test.map(|(t, _)| format!("{:.4}", t)).unwrap_or_else(|| "NA".into()),
+            format_optional(test.map(|(_, p)| p)),
+            format_optional(adjusted[i])
+        )?;
+    }
+    log::info!("Differential operon usage ({} vs {}) written to {}", condition, reference, diff_path);
+    log::info!("Per-sample ratios written to {}", ratios_path);
+    Ok(())
+}
@@ -0,0 +1,66 @@
+// eval.rs
+use std::{collections::{BTreeMap, BTreeSet, HashSet}, path::PathBuf};
+use crate::detect;
+
+#[derive(clap::Args, Debug)]
+pub struct EvalArgs {
+    /// Operons table to evaluate ('_operons_found_' TSV).
+    #[arg(long)]
+    predicted: PathBuf,
+
+    /// Truth set: TSV with the operon ID in the first column and a member transcript in the last one.
+    #[arg(long)]
+    truth: PathBuf,
+}
+
+// Members of each operon
+fn members_by_operon(rows: &[(String, String, String)]) -> BTreeMap<&str, BTreeSet<&str>> {
+    let mut operons: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (operon_id, _, member) in rows {
+        operons.entry(operon_id.as_str()).or_default().insert(member.as_str());
+    }
+    operons
+}
+
+// Unordered pairs of members sharing an operon
+fn member_pairs<'a>(operons: &BTreeMap<&'a str, BTreeSet<&'a str>>) -> HashSet<(&'a str, &'a str)> {
+    let mut pairs = HashSet::new();
+    for members in operons.values() {
+        for (i, m1) in members.iter().enumerate() {
+            for m2 in members.iter().skip(i + 1) {
+                pairs.insert((*m1, *m2));
+            }
+        }
+    }
+    pairs
+}
+
+fn print_row(level: &str, true_positives: usize, predicted: usize, truth: usize) {
+    let ratio = |n: usize, d: usize| if d == 0 { 0.0 } else { n as f64 / d as f64 };
+    let precision = ratio(true_positives, predicted);
+    let recall = ratio(true_positives, truth);
+    let f1 = if precision + recall == 0.0 { 0.0 } else { 2.0 * precision * recall / (precision + recall) };
+    println!("{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{:.4}", level, true_positives, predicted, truth, precision, recall, f1);
+}
+
+/// Prints precision/recall of the predicted operons against the truth set, at the level of
+/// OpGs, co-operonic OpG pairs and whole operons (identical member sets).
+pub fn run(args: EvalArgs) -> anyhow::Result<()> {
+    let predicted_rows = detect::read_operons(&args.predicted)?;
+    let truth_rows = detect::read_operons(&args.truth)?;
+    let predicted = members_by_operon(&predicted_rows);
+    let truth = members_by_operon(&truth_rows);
+
+    let predicted_members: HashSet<&str> = predicted.values().flatten().copied().collect();
+    let truth_members: HashSet<&str> = truth.values().flatten().copied().collect();
+    let predicted_pairs = member_pairs(&predicted);
+    let truth_pairs = member_pairs(&truth);
+    let truth_sets: HashSet<&BTreeSet<&str>> = truth.values().collect();
+    let exact = predicted.values().filter(|members| truth_sets.contains(members)).count();
+
+    println!("Level\tTP\tPredicted\tTruth\tPrecision\tRecall\tF1");
+    print_row("OpG", predicted_members.intersection(&truth_members).count(), predicted_members.len(), truth_members.len());
+    print_row("Pair", predicted_pairs.intersection(&truth_pairs).count(), predicted_pairs.len(), truth_pairs.len());
+    print_row("Operon", exact, predicted.len(), truth.len());
+    Ok(())
+}
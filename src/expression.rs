@@ -0,0 +1,89 @@
+// expression.rs
+use std::{collections::{HashMap, HashSet}, fs::File, io::{BufRead, BufReader, Write}, path::Path};
+use crate::gtf::Annotation;
+use crate::output::OutputPaths;
+
+/// Per-transcript expression of a quantifier table: (coverage, FPKM) by transcript ID.
+/// StringTie 't_data.ctab' gives 'cov' and 'FPKM'; Salmon 'quant.sf' and kallisto
+/// 'abundance.tsv' give TPM, used for both.
+pub fn read_expression(path: &Path) -> anyhow::Result<HashMap<String, (f32, f32)>> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    let columns: Vec<&str> = header.split('\t').collect();
+    let column = |name: &str| columns.iter().position(|c| *c == name);
+    let (id, coverage, fpkm, format) = if let (Some(id), Some(cov), Some(fpkm)) = (column("t_name"), column("cov"), column("FPKM")) {
+        (id, cov, fpkm, "StringTie t_data.ctab")
+    } else if let (Some(id), Some(tpm)) = (column("Name"), column("TPM")) {
+        (id, tpm, tpm, "Salmon quant.sf")
+    } else if let (Some(id), Some(tpm)) = (column("target_id"), column("tpm")) {
+        (id, tpm, tpm, "kallisto abundance.tsv")
+    } else {
+        anyhow::bail!("{}: unknown header, expected a Salmon quant.sf, kallisto abundance.tsv or StringTie t_data.ctab", path.display());
+    };
+    log::info!("Reading expression from {} ({})", path.display(), format);
+
+    let mut expression = HashMap::new();
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let parse = |j: usize| -> anyhow::Result<f32> {
+            fields.get(j)
+                .and_then(|f| f.parse().ok())
+                .ok_or_else(|| anyhow::anyhow!("{}:{}: invalid or missing column {}", path.display(), i + 2, j + 1))
+        };
+        let Some(transcript_id) = fields.get(id) else {
+            anyhow::bail!("{}:{}: missing column {}", path.display(), i + 2, id + 1);
+        };
+        expression.insert(transcript_id.to_string(), (parse(coverage)?, parse(fpkm)?));
+    }
+    Ok(expression)
+}
+
+/// Replaces the coverage and FPKM of the transcripts with the values of a quantifier table.
+/// Transcripts missing from the table get 0; unmatched IDs on either side are listed in
+/// `PREFIX_expression_unmatched.tsv`.
+pub fn fill_expression(annotation: &mut Annotation, path: &Path, out: &OutputPaths) -> anyhow::Result<()> {
+    let expression = read_expression(path)?;
+    let mut matched: HashSet<&str> = HashSet::new();
+    let mut missing_from_table: Vec<String> = Vec::new();
+    for t in annotation.transcripts_by_chrom.values_mut().flatten() {
+        match expression.get_key_value(&t.id) {
+            Some((id, &(coverage, fpkm))) => {
+                t.coverage = coverage;
+                t.fpkm_val = fpkm;
+                matched.insert(id.as_str());
+            }
+            None => {
+                t.coverage = 0.0;
+                t.fpkm_val = 0.0;
+                missing_from_table.push(t.id.clone());
+            }
+        }
+    }
+    let mut missing_from_annotation: Vec<&str> = expression.keys().map(|id| id.as_str()).filter(|id| !matched.contains(id)).collect();
+    missing_from_annotation.sort();
+
+    let unmatched_path = format!("{}_expression_unmatched.tsv", out.prefix);
+    let mut file = out.create(unmatched_path.clone())?;
+    writeln!(file, "Transcript\tMissing_from")?;
+    for id in &missing_from_table {
+        writeln!(file, "{}\ttable", id)?;
+    }
+    for id in &missing_from_annotation {
+        writeln!(file, "{}\tannotation", id)?;
+    }
+    log::info!("Expression of {} transcripts taken from {}", matched.len(), path.display());
+    if !missing_from_table.is_empty() {
+        log::warn!("{} transcripts of the annotation not found in {} (expression set to 0)", missing_from_table.len(), path.display());
+    }
+    if !missing_from_annotation.is_empty() {
+        log::warn!("{} transcripts of {} not found in the annotation", missing_from_annotation.len(), path.display());
+    }
+    if !missing_from_table.is_empty() || !missing_from_annotation.is_empty() {
+        log::info!("Unmatched transcript IDs written to {}", unmatched_path);
+    }
+    Ok(())
+}
@@ -0,0 +1,289 @@
+// gtf.rs
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs::File, io::{BufRead, BufReader, Write}, path::{Path, PathBuf}};
+use noodles::{bgzf, core::{Position, region::Interval}, csi::BinningIndex, gff::feature::RecordBuf, gtf};
+
+#[derive(Debug, Clone)]
+pub struct Transcript {
+    pub id: String,
+    pub gene_id: String,
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+    pub strand: String,
+    pub coverage: f32,
+    pub fpkm_val: f32,
+    pub score: f32,
+    pub exons: Vec<(u64, u64)>,
+    pub raw_lines: Vec<String>,
+}
+
+/// Transcripts of an input GTF, grouped by chromosome, with their original GTF lines.
+pub struct Annotation {
+    pub transcripts_by_chrom: BTreeMap<String, Vec<Transcript>>,
+    pub raw_lines_by_id: HashMap<String, Vec<String>>,
+}
+
+impl Annotation {
+    /// Transcripts by ID.
+    pub fn by_id(&self) -> HashMap<&str, &Transcript> {
+        transcripts_by_id(&self.transcripts_by_chrom)
+    }
+}
+
+/// Transcripts of a per-chromosome map by ID.
+pub fn transcripts_by_id(transcripts_by_chrom: &BTreeMap<String, Vec<Transcript>>) -> HashMap<&str, &Transcript> {
+    transcripts_by_chrom.values().flatten().map(|t| (t.id.as_str(), t)).collect()
+}
+
+/// Single value of a string attribute; a multi-valued one is an error.
+pub fn string_attribute(record: &RecordBuf, key: &str) -> anyhow::Result<Option<String>> {
+    match record.attributes().get(key.as_ref()) {
+        None => Ok(None),
+        Some(value) => value.as_string().map(|s| Some(s.to_string())).ok_or_else(|| anyhow::anyhow!("attribute {} has several values", key)),
+    }
+}
+
+// Numeric attribute, 0 when missing.
+fn numeric_attribute(record: &RecordBuf, key: &str) -> anyhow::Result<f32> {
+    match string_attribute(record, key)? {
+        None => Ok(0.0),
+        Some(value) => value.parse::<f32>().map_err(|_| anyhow::anyhow!("attribute {} is not a number: '{}'", key, value)),
+    }
+}
+
+/// Record as stored in `Annotation.raw_lines_by_id`, the form `write_gtf` expects.
+pub fn raw_line(record: &RecordBuf) -> anyhow::Result<String> {
+    let mut writer = noodles::gff::io::Writer::new(Vec::new());
+    writer.write_record(record)?;
+    Ok(String::from_utf8(writer.into_inner())?)
+}
+
+// Collects transcripts, exons and raw lines from GTF records. Indexed reads set `seen` to skip
+// records already read, as overlapping region queries may return the same line twice.
+#[derive(Default)]
+struct AnnotationBuilder {
+    transcripts_by_chrom: BTreeMap<String, Vec<Transcript>>,
+    exons_by_transcript: HashMap<String, Vec<(u64, u64)>>,
+    raw_lines_by_id: HashMap<String, Vec<String>>,
+    seen: Option<HashSet<String>>,
+    skip_invalid: bool,
+    skipped: usize,
+}
+
+impl AnnotationBuilder {
+    fn new(skip_invalid: bool) -> Self {
+        AnnotationBuilder { skip_invalid, ..Default::default() }
+    }
+
+    fn add(&mut self, record: RecordBuf) -> anyhow::Result<()> {
+        if record.ty() != "transcript" && record.ty() != "exon" {
+            return Ok(());
+        }
+        let tid = string_attribute(&record, "transcript_id")?.unwrap_or("NA".into());
+        let raw_line = raw_line(&record)?;
+        if self.seen.as_ref().is_some_and(|seen| seen.contains(&raw_line)) {
+            return Ok(());
+        }
+
+        if record.ty() == "transcript" {
+            let transcript = Transcript {
+                id: tid.clone(),
+                gene_id: string_attribute(&record, "gene_id")?.unwrap_or("NA".into()),
+                chrom: record.reference_sequence_name().to_string(),
+                start: record.start().get() as u64,
+                end: record.end().get() as u64,
+                strand: format!("{:?}", record.strand()),
+                coverage: numeric_attribute(&record, "cov")?,
+                fpkm_val: numeric_attribute(&record, "FPKM")?,
+                score: record.score().unwrap_or(0.0),
+                exons: Vec::new(),
+                raw_lines: vec![format!("{:?}", record)],
+            };
+            self.transcripts_by_chrom.entry(transcript.chrom.clone()).or_default().push(transcript);
+        } else {
+            let start = record.start().get();
+            let end = record.end().get();
+            self.exons_by_transcript.entry(tid.clone()).or_default().push((start as u64, end as u64));
+        }
+        if let Some(seen) = self.seen.as_mut() {
+            seen.insert(raw_line.clone());
+        }
+        self.raw_lines_by_id.entry(tid).or_default().push(raw_line);
+        Ok(())
+    }
+
+    fn finish(self) -> Annotation {
+        let mut transcripts_by_chrom = self.transcripts_by_chrom;
+        for transcripts in transcripts_by_chrom.values_mut() {
+            for transcript in transcripts.iter_mut() {
+                if let Some(exons) = self.exons_by_transcript.get(&transcript.id) {
+                    transcript.exons = exons.clone();
+                }
+                if let Some(lines) = self.raw_lines_by_id.get(&transcript.id) {
+                    transcript.raw_lines = lines.clone();
+                }
+            }
+        }
+        if self.skipped > 0 {
+            log::warn!("Skipped {} invalid records", self.skipped);
+        }
+        Annotation { transcripts_by_chrom, raw_lines_by_id: self.raw_lines_by_id }
+    }
+}
+
+// Calls `add` on every record read from `reader` and returns the number of invalid records
+// skipped with `skip_invalid` (otherwise the first one is an error). Errors are located by line
+// number on sequential reads, and by the queried region (a whole chromosome when the interval
+// is `None`) on indexed reads, whose records outside the region are ignored.
+fn read_lines<R: BufRead>(
+    reader: &mut gtf::io::Reader<R>,
+    source: &Path,
+    query: Option<&(String, Option<(u64, u64)>)>,
+    skip_invalid: bool,
+    mut add: impl FnMut(RecordBuf) -> anyhow::Result<()>,
+) -> anyhow::Result<usize> {
+    let mut line = gtf::Line::default();
+    let mut line_number = 0;
+    let mut skipped = 0;
+    loop {
+        let location = match query {
+            None => format!("{}:{}", source.display(), line_number + 1),
+            Some((chrom, None)) => format!("{} (query {})", source.display(), chrom),
+            Some((chrom, Some((start, end)))) => format!("{} (query {}:{}-{})", source.display(), chrom, start, end),
+        };
+        if reader.read_line(&mut line).map_err(|e| anyhow::anyhow!("{}: {}", location, e))? == 0 {
+            break;
+        }
+        line_number += 1;
+        let text = line.as_ref().to_string();
+        if text.is_empty() {
+            continue;
+        }
+        let Some(record) = line.as_record() else { continue };
+        let record = record.and_then(|r| RecordBuf::try_from_feature_record(&r));
+        if let (Ok(record), Some((chrom, interval))) = (&record, query) {
+            let outside = match interval {
+                Some((start, end)) => (record.start().get() as u64) > *end || (record.end().get() as u64) < *start,
+                None => false,
+            };
+            if record.reference_sequence_name() != chrom.as_str() || outside {
+                continue;
+            }
+        }
+        if let Err(e) = record.map_err(anyhow::Error::from).and_then(&mut add) {
+            if !skip_invalid {
+                anyhow::bail!("invalid record at {}: {}\n    {} (use --skip-invalid to skip such records)", location, e, text);
+            }
+            log::warn!("Skipping invalid record at {}: {}\n    {}", location, e, text);
+            skipped += 1;
+        }
+    }
+    Ok(skipped)
+}
+
+/// Calls `add` on every record of a GTF, plain or BGZF-compressed, and returns the number of
+/// invalid records skipped with `skip_invalid` (otherwise the first one is an error).
+pub fn read_records(gtf_path: &Path, skip_invalid: bool, add: impl FnMut(RecordBuf) -> anyhow::Result<()>) -> anyhow::Result<usize> {
+    let file = File::open(gtf_path).map_err(|e| anyhow::anyhow!("{}: {}", gtf_path.display(), e))?;
+    if is_bgzf(gtf_path) {
+        read_lines(&mut gtf::io::Reader::new(bgzf::io::Reader::new(file)), gtf_path, None, skip_invalid, add)
+    } else {
+        read_lines(&mut gtf::io::Reader::new(BufReader::new(file)), gtf_path, None, skip_invalid, add)
+    }
+}
+
+/// Whether the file is BGZF-compressed, judging by its extension.
+pub fn is_bgzf(gtf_path: &Path) -> bool {
+    matches!(gtf_path.extension().and_then(|e| e.to_str()), Some("gz" | "bgz"))
+}
+
+/// Reads the whole GTF, plain or BGZF-compressed. Invalid records are an error, or are
+/// logged and skipped with `skip_invalid`.
+pub fn read_annotation(gtf_path: &Path, skip_invalid: bool) -> anyhow::Result<Annotation> {
+    let mut builder = AnnotationBuilder::new(skip_invalid);
+    builder.skipped = read_records(gtf_path, skip_invalid, |record| builder.add(record))?;
+    Ok(builder.finish())
+}
+
+/// Tabix (`.tbi`) or CSI (`.csi`) index next to a BGZF-compressed GTF, if any.
+pub fn find_index(gtf_path: &Path) -> Option<PathBuf> {
+    if !is_bgzf(gtf_path) {
+        return None;
+    }
+    ["tbi", "csi"].iter().map(|ext| PathBuf::from(format!("{}.{}", gtf_path.display(), ext))).find(|p| p.exists())
+}
+
+fn query_records<I: BinningIndex>(gtf_path: &Path, index: &I, queries: &[(String, Option<(u64, u64)>)], builder: &mut AnnotationBuilder) -> anyhow::Result<()> {
+    let mut reader = bgzf::io::Reader::new(File::open(gtf_path)?);
+    let Some(header) = index.header() else { anyhow::bail!("{}: missing index header", gtf_path.display()) };
+    for query in queries {
+        let (chrom, interval) = query;
+        let Some(reference_sequence_id) = header.reference_sequence_names().get_index_of(chrom.as_bytes()) else {
+            log::warn!("{} is not in the index of {}", chrom, gtf_path.display());
+            continue;
+        };
+        let interval: Interval = match interval {
+            Some((start, end)) => (Position::try_from(*start as usize)?..=Position::try_from(*end as usize)?).into(),
+            None => (..).into(),
+        };
+        let chunks = index.query(reference_sequence_id, interval)?;
+        let mut records = gtf::io::Reader::new(noodles::csi::io::Query::new(&mut reader, chunks));
+        builder.skipped += read_lines(&mut records, gtf_path, Some(query), builder.skip_invalid, |record| builder.add(record))?;
+    }
+    Ok(())
+}
+
+/// Reads only the records overlapping the queries (a whole chromosome when the interval is
+/// `None`) from a BGZF-compressed GTF, using its tabix or CSI index.
+pub fn read_annotation_indexed(gtf_path: &Path, index_path: &Path, queries: &[(String, Option<(u64, u64)>)], skip_invalid: bool) -> anyhow::Result<Annotation> {
+    let mut builder = AnnotationBuilder::new(skip_invalid);
+    builder.seen = Some(HashSet::new());
+    if index_path.extension().is_some_and(|e| e == "csi") {
+        let index = noodles::csi::fs::read(index_path)?;
+        query_records(gtf_path, &index, queries, &mut builder)?;
+    } else {
+        let index = noodles::tabix::fs::read(index_path)?;
+        query_records(gtf_path, &index, queries, &mut builder)?;
+    }
+    Ok(builder.finish())
+}
+
+/// Writes the original GTF lines of the given transcript IDs, ordered by ID.
+pub fn write_gtf(file: &mut dyn Write, ids: &HashSet<String>, raw_lines_by_id: &HashMap<String, Vec<String>>) -> anyhow::Result<()> {
+    write_gtf_with_attributes(file, ids, raw_lines_by_id, &HashMap::new())
+}
+
+/// Same as `write_gtf`, appending to every line of a transcript its extra attributes
+/// (name, value).
+pub fn write_gtf_with_attributes(
+    file: &mut dyn Write,
+    ids: &HashSet<String>,
+    raw_lines_by_id: &HashMap<String, Vec<String>>,
+    attributes: &HashMap<String, Vec<(&str, String)>>,
+) -> anyhow::Result<()> {
+    let mut ids_ordered = Vec::from_iter(ids);
+    // StringTie IDs (MSTRG.N.M) by gene number, then any other ID by name
+    ids_ordered.sort_by_key(|id| (id.split(".").nth(1).and_then(|n| n.parse::<u32>().ok()).unwrap_or(u32::MAX), id.to_string()));
+
+    for id in ids_ordered {
+        if let Some(lines) = raw_lines_by_id.get(id) {
+            let extra: String = attributes
+                .get(id)
+                .map(|attributes| attributes.iter().map(|(name, value)| format!("{} \"{}\";", name, value)).collect())
+                .unwrap_or_default();
+            for line in lines {
+                writeln!(file, "{}{}", line.replace("\n", ";").replace("=", " \"").replace(";", "\";"), extra)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// GTF strand symbol of a `Transcript.strand`.
+pub fn strand_symbol(strand: &str) -> &'static str {
+    match strand {
+        "Forward" => "+",
+        "Reverse" => "-",
+        _ => ".",
+    }
+}
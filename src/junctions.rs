@@ -0,0 +1,89 @@
+// junctions.rs
+use std::{collections::BTreeMap, fs::File, io::{BufRead, BufReader}, path::Path};
+use crate::gtf::Transcript;
+
+/// Splice junction with its intron coordinates (1-based, inclusive).
+#[derive(Debug, Clone)]
+pub struct Junction {
+    pub start: u64,
+    pub end: u64,
+    /// '+', '-' or '.' when undefined.
+    pub strand: char,
+    pub reads: u64,
+}
+
+/// Junctions of a STAR 'SJ.out.tab' (unique reads) or of a regtools BED12 (score), by
+/// chromosome and intron start.
+pub fn read_junctions(path: &Path) -> anyhow::Result<BTreeMap<String, Vec<Junction>>> {
+    let mut junctions: BTreeMap<String, Vec<Junction>> = BTreeMap::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let parse = |j: usize| -> anyhow::Result<u64> {
+            fields.get(j)
+                .and_then(|f| f.parse().ok())
+                .ok_or_else(|| anyhow::anyhow!("{}:{}: invalid or missing column {}", path.display(), i + 1, j + 1))
+        };
+        let junction = match fields.len() {
+            // STAR: intron start/end, strand (0 undefined, 1 +, 2 -), motif, annotated, unique, multi, overhang
+            9 => Junction {
+                start: parse(1)?,
+                end: parse(2)?,
+                strand: match fields[3] {
+                    "1" => '+',
+                    "2" => '-',
+                    _ => '.',
+                },
+                reads: parse(6)?,
+            },
+            // regtools: the two blocks are the anchors around the intron
+            n if n >= 12 => {
+                let sizes: Vec<u64> = fields[10].split(',').filter(|s| !s.is_empty()).filter_map(|s| s.parse().ok()).collect();
+                if sizes.len() != 2 {
+                    anyhow::bail!("{}:{}: expected two blocks", path.display(), i + 1);
+                }
+                let end = parse(2)?
+                    .checked_sub(sizes[1])
+                    .ok_or_else(|| anyhow::anyhow!("{}:{}: block size {} larger than the end", path.display(), i + 1, sizes[1]))?;
+                Junction {
+                    start: parse(1)? + sizes[0] + 1,
+                    end,
+                    strand: fields[5].chars().next().unwrap_or('.'),
+                    reads: parse(4)?,
+                }
+            }
+            _ => anyhow::bail!("{}:{}: neither a STAR SJ.out.tab nor a regtools BED12 line", path.display(), i + 1),
+        };
+        if junction.start == 0 || junction.start > junction.end {
+            anyhow::bail!("{}:{}: invalid intron {}-{}", path.display(), i + 1, junction.start, junction.end);
+        }
+        junctions.entry(fields[0].to_string()).or_default().push(junction);
+    }
+    for chrom_junctions in junctions.values_mut() {
+        chrom_junctions.sort_by_key(|j| (j.start, j.end));
+    }
+    Ok(junctions)
+}
+
+/// Junctions (count, reads) leaving the leftmost of the two transcripts and landing in the
+/// other one, on a compatible strand.
+pub fn bridging_support(junctions: &BTreeMap<String, Vec<Junction>>, t1: &Transcript, t2: &Transcript) -> (usize, u64) {
+    let (left, right) = if t1.start <= t2.start { (t1, t2) } else { (t2, t1) };
+    let Some(chrom_junctions) = junctions.get(&left.chrom) else { return (0, 0) };
+    let strand = match left.strand.as_str() {
+        "Forward" => '+',
+        "Reverse" => '-',
+        _ => '.',
+    };
+    let first = chrom_junctions.partition_point(|j| j.start <= left.start);
+    let bridging: Vec<&Junction> = chrom_junctions[first..]
+        .iter()
+        .take_while(|j| j.start - 1 <= left.end)
+        .filter(|j| j.end < right.end && j.end + 1 >= right.start)
+        .filter(|j| j.strand == '.' || strand == '.' || j.strand == strand)
+        .collect();
+    (bridging.len(), bridging.iter().map(|j| j.reads).sum())
+}
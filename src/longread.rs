@@ -0,0 +1,308 @@
+// longread.rs
+use std::{collections::{BTreeMap, HashMap}, io::Write, path::PathBuf};
+use itertools::Itertools;
+use noodles::{bam, sam::alignment::record::cigar::op::Kind};
+use crate::gtf;
+use crate::output::{OutputArgs, OutputPaths, Provenance};
+
+#[derive(clap::Args, Debug)]
+pub struct LongReadArgs {
+    /// Long-read alignments (BAM).
+    #[arg(long)]
+    bam: PathBuf,
+
+    /// Gene annotation (GTF with transcript and exon records).
+    #[arg(short, long)]
+    annotation: PathBuf,
+
+    /// Minimum exonic overlap (bp) of a read with a gene to count the gene as covered.
+    #[arg(long, default_value_t = 50)]
+    min_gene_overlap: u64,
+
+    /// Minimum number of reads bridging two consecutive genes to join them in an OPRN.
+    #[arg(long, default_value_t = 2)]
+    min_reads: usize,
+
+    /// Minimum mapping quality of the alignments.
+    #[arg(long, default_value_t = 1)]
+    min_mapq: u8,
+
+    /// Require reads to align on the strand of the genes (direct RNA, stranded cDNA).
+    #[arg(long)]
+    stranded: bool,
+
+    /// Log and skip malformed GTF records instead of stopping at the first one.
+    #[arg(long)]
+    skip_invalid: bool,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+// Gene of the annotation, with the merged exons of its isoforms
+struct Gene {
+    id: String,
+    start: u64,
+    end: u64,
+    strand: String,
+    exons: Vec<(u64, u64)>,
+    // Largest end of the genes up to this one, to find the first gene overlapping a position
+    max_end: u64,
+}
+
+// Genes by chromosome, sorted by start
+fn genes_by_chrom(annotation: &gtf::Annotation) -> BTreeMap<String, Vec<Gene>> {
+    let mut genes_by_chrom: BTreeMap<String, Vec<Gene>> = BTreeMap::new();
+    for (chrom, transcripts) in &annotation.transcripts_by_chrom {
+        let mut by_gene: BTreeMap<&str, Vec<&gtf::Transcript>> = BTreeMap::new();
+        for transcript in transcripts {
+            by_gene.entry(transcript.gene_id.as_str()).or_default().push(transcript);
+        }
+        let mut genes: Vec<Gene> = by_gene
+            .into_iter()
+            .map(|(gene_id, isoforms)| {
+                let mut exons: Vec<(u64, u64)> = isoforms.iter().flat_map(|t| t.exons.iter().copied()).collect();
+                exons.sort();
+                let mut merged: Vec<(u64, u64)> = Vec::new();
+                for (start, end) in exons {
+                    match merged.last_mut() {
+                        Some((_, last_end)) if start <= *last_end => *last_end = (*last_end).max(end),
+                        _ => merged.push((start, end)),
+                    }
+                }
+                Gene {
+                    id: gene_id.to_string(),
+                    start: isoforms.iter().map(|t| t.start).min().unwrap_or(0),
+                    end: isoforms.iter().map(|t| t.end).max().unwrap_or(0),
+                    strand: isoforms[0].strand.clone(),
+                    exons: merged,
+                    max_end: 0,
+                }
+            })
+            .collect();
+        genes.sort_by_key(|g| (g.start, g.end));
+        let mut max_end = 0;
+        for gene in genes.iter_mut() {
+            max_end = max_end.max(gene.end);
+            gene.max_end = max_end;
+        }
+        genes_by_chrom.insert(chrom.clone(), genes);
+    }
+    genes_by_chrom
+}
+
+// Aligned reference blocks (1-based, inclusive) of a record, split at skipped regions (introns)
+pub(crate) fn aligned_blocks(record: &bam::Record) -> anyhow::Result<Vec<(u64, u64)>> {
+    let Some(start) = record.alignment_start().transpose()? else { return Ok(Vec::new()) };
+    let mut pos = start.get() as u64;
+    let mut blocks: Vec<(u64, u64)> = Vec::new();
+    let mut block_start = pos;
+    for op in record.cigar().iter() {
+        let op = op?;
+        let len = op.len() as u64;
+        match op.kind() {
+            Kind::Match | Kind::SequenceMatch | Kind::SequenceMismatch | Kind::Deletion => pos += len,
+            Kind::Skip => {
+                if pos > block_start {
+                    blocks.push((block_start, pos - 1));
+                }
+                pos += len;
+                block_start = pos;
+            }
+            _ => {}
+        }
+    }
+    if pos > block_start {
+        blocks.push((block_start, pos - 1));
+    }
+    Ok(blocks)
+}
+
+// Indices of the genes whose exons overlap the blocks by at least `min_overlap` bp
+fn genes_hit(genes: &[Gene], blocks: &[(u64, u64)], min_overlap: u64) -> Vec<usize> {
+    let (Some(first), Some(last)) = (blocks.first(), blocks.last()) else { return Vec::new() };
+    let candidates = genes.partition_point(|g| g.max_end < first.0);
+    genes.iter()
+        .enumerate()
+        .skip(candidates)
+        .take_while(|(_, g)| g.start <= last.1)
+        .filter(|(_, g)| g.end >= first.0)
+        .filter(|(_, g)| exonic_overlap(&g.exons, blocks) >= min_overlap)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+// Summed overlap (bp) between two sets of intervals
+pub(crate) fn exonic_overlap(a: &[(u64, u64)], b: &[(u64, u64)]) -> u64 {
+    let mut overlap = 0;
+    for (s1, e1) in a {
+        for (s2, e2) in b {
+            if s1 <= e2 && s2 <= e1 {
+                overlap += e1.min(e2) - s1.max(s2) + 1;
+            }
+        }
+    }
+    overlap
+}
+
+// Polycistronic molecule: a read covering two or more same-strand genes
+struct Molecule {
+    read: String,
+    chrom: String,
+    /// Gene indices, by position.
+    genes: Vec<usize>,
+}
+
+fn find(parent: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parent[root] != root {
+        root = parent[root];
+    }
+    parent[i] = root;
+    root
+}
+
+/// Counts the reads spanning two or more same-strand genes, and joins consecutive genes
+/// bridged by at least `min_reads` reads into OPRNs.
+pub fn run(args: LongReadArgs) -> anyhow::Result<()> {
+    let mut out = OutputPaths::new(&args.bam, &args.output)?;
+    out.init_log()?;
+    out.provenance = Some(Provenance::new(&args.bam, &[
+        ("annotation", args.annotation.display().to_string()),
+        ("min_gene_overlap", args.min_gene_overlap.to_string()),
+        ("min_reads", args.min_reads.to_string()),
+        ("min_mapq", args.min_mapq.to_string()),
+        ("stranded", args.stranded.to_string()),
+    ])?);
+
+    let annotation = gtf::read_annotation(&args.annotation, args.skip_invalid)?;
+    let genes_by_chrom = genes_by_chrom(&annotation);
+    log::info!("Read {} genes from {}", genes_by_chrom.values().map(|g| g.len()).sum::<usize>(), args.annotation.display());
+
+    let mut reader = bam::io::reader::Builder.build_from_path(&args.bam)?;
+    let header = reader.read_header()?;
+    let mut molecules: Vec<Molecule> = Vec::new();
+    let mut used_reads = 0;
+    for result in reader.records() {
+        let record = result?;
+        let flags = record.flags();
+        if flags.is_unmapped() || flags.is_secondary() || flags.is_supplementary() || flags.is_qc_fail() || flags.is_duplicate() {
+            continue;
+        }
+        if record.mapping_quality().is_some_and(|q| q.get() < args.min_mapq) {
+            continue;
+        }
+        let Some(reference_sequence_id) = record.reference_sequence_id().transpose()? else { continue };
+        let Some((chrom, _)) = header.reference_sequences().get_index(reference_sequence_id) else { continue };
+        let chrom = chrom.to_string();
+        used_reads += 1;
+        let Some(genes) = genes_by_chrom.get(&chrom) else { continue };
+        let hit = genes_hit(genes, &aligned_blocks(&record)?, args.min_gene_overlap);
+        let read_strand = if flags.is_reverse_complemented() { "Reverse" } else { "Forward" };
+        for strand in ["Forward", "Reverse"] {
+            if args.stranded && strand != read_strand {
+                continue;
+            }
+            let same_strand: Vec<usize> = hit.iter().copied().filter(|&i| genes[i].strand == strand).collect();
+            if same_strand.len() >= 2 {
+                let read = record.name().map(|n| n.to_string()).unwrap_or_else(|| "*".into());
+                molecules.push(Molecule { read, chrom: chrom.clone(), genes: same_strand });
+            }
+        }
+    }
+    log::info!("Alignments used: {}; polycistronic molecules: {}", used_reads, molecules.len());
+
+    // Support of each pair of consecutive genes of the molecules, by chromosome, then OPRNs as
+    // runs of supported pairs
+    let mut pair_reads: HashMap<&str, HashMap<(usize, usize), usize>> = HashMap::new();
+    for molecule in &molecules {
+        for pair in molecule.genes.windows(2) {
+            *pair_reads.entry(molecule.chrom.as_str()).or_default().entry((pair[0], pair[1])).or_default() += 1;
+        }
+    }
+    let mut operons: Vec<(String, String, Vec<usize>)> = Vec::new();
+    let mut operon_of: HashMap<(&str, usize), usize> = HashMap::new();
+    for (chrom, genes) in &genes_by_chrom {
+        let Some(chrom_pairs) = pair_reads.get(chrom.as_str()) else { continue };
+        let mut parent: Vec<usize> = (0..genes.len()).collect();
+        for (&(g1, g2), &reads) in chrom_pairs {
+            if reads >= args.min_reads {
+                let (r1, r2) = (find(&mut parent, g1), find(&mut parent, g2));
+                parent[r2.max(r1)] = r1.min(r2);
+            }
+        }
+        let mut components: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for i in 0..genes.len() {
+            let root = find(&mut parent, i);
+            components.entry(root).or_default().push(i);
+        }
+        for members in components.into_values().filter(|m| m.len() >= 2) {
+            let operon_id = format!("OPRN.{}", operons.len() + 1);
+            for &i in &members {
+                operon_of.insert((chrom.as_str(), i), operons.len());
+            }
+            operons.push((operon_id, chrom.clone(), members));
+        }
+    }
+
+    let mut operon_reads = vec![0; operons.len()];
+    let reads_path = format!("{}_longread_reads.tsv", out.prefix);
+    let mut reads_file = out.create(reads_path.clone())?;
+    writeln!(reads_file, "Read\tChrom\tStrand\tGenes\tOperon")?;
+    for molecule in &molecules {
+        let genes = &genes_by_chrom[&molecule.chrom];
+        let mut ordered = molecule.genes.clone();
+        if genes[ordered[0]].strand == "Reverse" {
+            ordered.reverse();
+        }
+        // Every OPRN holding one of its genes; it supports those holding two or more
+        let touched: Vec<usize> = ordered.iter().filter_map(|&i| operon_of.get(&(molecule.chrom.as_str(), i)).copied()).unique().collect();
+        for &o in &touched {
+            if molecule.genes.iter().filter(|&&i| operon_of.get(&(molecule.chrom.as_str(), i)) == Some(&o)).count() >= 2 {
+                operon_reads[o] += 1;
+            }
+        }
+        writeln!(
+            reads_file,
+            "{}\t{}\t{}\t{}\t{}",
+            molecule.read,
+            molecule.chrom,
+            gtf::strand_symbol(&genes[ordered[0]].strand),
+            ordered.iter().map(|&i| genes[i].id.as_str()).collect::<Vec<_>>().join(","),
+            if touched.is_empty() { ".".to_string() } else { touched.iter().map(|&o| operons[o].0.as_str()).join(",") }
+        )?;
+    }
+
+    let operons_path = format!("{}_longread_operons.tsv", out.prefix);
+    let mut operons_file = out.create(operons_path.clone())?;
+    writeln!(operons_file, "Operon\tChrom\tStart\tEnd\tStrand\tGenes\tReads\tPair_reads")?;
+    for (o, (operon_id, chrom, members)) in operons.iter().enumerate() {
+        let genes = &genes_by_chrom[chrom];
+        let mut ordered = members.clone();
+        if genes[ordered[0]].strand == "Reverse" {
+            ordered.reverse();
+        }
+        let pairs: Vec<String> = ordered
+            .windows(2)
+            .map(|pair| {
+                let (g1, g2) = (pair[0].min(pair[1]), pair[0].max(pair[1]));
+                pair_reads.get(chrom.as_str()).and_then(|chrom_pairs| chrom_pairs.get(&(g1, g2))).copied().unwrap_or(0).to_string()
+            })
+            .collect();
+        writeln!(
+            operons_file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            operon_id,
+            chrom,
+            members.iter().map(|&i| genes[i].start).min().unwrap_or(0),
+            members.iter().map(|&i| genes[i].end).max().unwrap_or(0),
+            gtf::strand_symbol(&genes[ordered[0]].strand),
+            ordered.iter().map(|&i| genes[i].id.as_str()).collect::<Vec<_>>().join(","),
+            operon_reads[o],
+            pairs.join(",")
+        )?;
+    }
+    log::info!("OPRNs supported by at least {} reads per gene pair: {}", args.min_reads, operons.len());
+    log::info!("Output written to {} and {}", operons_path, reads_path);
+    Ok(())
+}
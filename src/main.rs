@@ -1,26 +1,79 @@
 // gamba-tool.rs
-use std::{cmp::Ordering, collections::{BTreeMap, HashMap, HashSet}, fs::File, io::{BufReader, BufWriter, Write}, path::PathBuf};
+mod compare;
+mod config;
+mod convert;
+mod coverage;
+mod detect;
+mod diff;
+mod eval;
+mod expression;
+mod gtf;
+mod junctions;
+mod longread;
+mod output;
+mod polarity;
+mod predict;
+mod prokaryote;
+mod regions;
+mod sequence;
+mod stats;
+mod sweep;
+
+use std::{collections::{HashMap, HashSet}, io::Write, path::PathBuf};
 use std::fmt::Debug;
-use clap::Parser;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
 use itertools::Itertools;
-use noodles::gtf;
-use ftail::Ftail;
-use log::LevelFilter;
-use std::path::Path;
-//use noodles::core::Position;
-//use noodles::gff::record::attributes::field::Value;
-
-type GeneId = String;
+use detect::{ConflictPolicy, Detection, Measure, Objective, OperonSpan, Params, Selection};
+use gtf::Annotation;
+use output::{OutputArgs, OutputPaths, Provenance};
+use regions::RegionFilter;
+use sequence::Genome;
+use sweep::SweepGrid;
 
 #[derive(Parser, Debug)]
 #[command(
     name = "GAMBA",
     version = env!("CARGO_PKG_VERSION"),
     about = "Detect polycistronic transcriptional units (operons) from a GTF file using coverage filtering.",
-    long_about = None
+    long_about = None,
+    args_conflicts_with_subcommands = true
 )]
-struct Args {
-    /// Path to the input GTF file.
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Running gamba without a subcommand is the same as 'gamba detect'. clap cannot tell
+    /// whether a flattened struct with nested flattened options was given, so `main` parses it
+    /// whenever the command line has arguments.
+    #[command(flatten)]
+    detect: Option<DetectArgs>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Detect operons in a GTF file (default).
+    Detect(Box<DetectArgs>),
+    /// Print summary statistics of an input GTF file.
+    Stats(stats::StatsArgs),
+    /// Convert a GTF file (e.g. a gamba output) to BED12 or GFF3.
+    Convert(convert::ConvertArgs),
+    /// Compare an operons table against a truth set.
+    Eval(eval::EvalArgs),
+    /// Regenerate the output files from a previous run's operons table.
+    Export(ExportArgs),
+    /// Call operons from long-read alignments (BAM) and a gene annotation.
+    Longread(longread::LongReadArgs),
+    /// Compare the container/OpG expression ratios of the operons between two conditions.
+    Diff(diff::DiffArgs),
+    /// Predict candidate operons from a gene annotation alone (same-strand runs of close genes).
+    Predict(PredictArgs),
+    /// Compare the operons of two species or populations through an orthology table.
+    Compare(compare::CompareArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct DetectArgs {
+    /// Path to the input GTF file (plain, or BGZF-compressed with an optional tabix/CSI index).
     #[arg(short, long)]
     file: PathBuf,
 
@@ -29,412 +82,792 @@ struct Args {
     threshold: f32,
     
     /// Coverage threshold multiplier for monoexonic transcripts.
-    #[arg(long, default_value_t = 10.0)]
+    #[arg(long, default_value_t = 10.0, conflicts_with = "prokaryote")]
     monoexonic_t: f32,
     
     /// Minimum percentage of exonic overlap to be considered 'contained transcript'.
-    #[arg(short, long, default_value_t = 0.5)]
+    #[arg(short, long, default_value_t = 0.5, conflicts_with = "prokaryote")]
     min_overlap: f32,
     
     /// Minimum bp overlap to consider exonic overlap.
-    #[arg(short, long, default_value_t = 50)]
+    #[arg(short, long, default_value_t = 50, conflicts_with = "prokaryote")]
     bp_overlap: u64,
 
-    /// Output file prefix.
-    #[arg(short, long)]
-    prefix: Option<String>,
+    /// Boundary tolerance (bp) for containment and for clustering containers into OPRNs.
+    #[arg(long, default_value_t = 250)]
+    tolerance: u64,
+
+    /// Overlap tolerance (bp) allowed between OpGs.
+    #[arg(long, default_value_t = 50)]
+    opg_tolerance: u64,
+
+    /// TOML file with parameter values, used for the parameters not given on the command line.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Built-in parameter set, used for the parameters not given on the command line or in '--config'.
+    #[arg(long, value_enum)]
+    preset: Option<config::Preset>,
+
+    /// Restrict the analysis to a region 'chr:start-end' (1-based, inclusive; can be repeated).
+    #[arg(long = "region", value_name = "REGION", value_parser = regions::parse_region)]
+    regions: Vec<regions::Region>,
+
+    /// Restrict the analysis to these chromosomes (comma-separated).
+    #[arg(long, value_delimiter = ',')]
+    chroms: Vec<String>,
+
+    /// Restrict the analysis to the regions of a BED file.
+    #[arg(long)]
+    regions_bed: Option<PathBuf>,
+
+    /// Log and skip malformed GTF records instead of stopping at the first one.
+    #[arg(long)]
+    skip_invalid: bool,
+
+    /// Compute the coverage of every transcript (mean exonic depth) from a BAM or bedGraph,
+    /// in place of the 'cov' attribute.
+    #[arg(long, value_name = "FILE")]
+    coverage_from: Option<PathBuf>,
+
+    /// Take the coverage and FPKM of every transcript from a Salmon 'quant.sf', kallisto
+    /// 'abundance.tsv' or StringTie 't_data.ctab', joined on transcript_id.
+    #[arg(long, value_name = "FILE", conflicts_with = "coverage_from")]
+    expression: Option<PathBuf>,
+
+    /// Prokaryotic mode: the OpGs are the genes of '--genes' covered by each transcript, instead
+    /// of the contained transcripts and their exons; the coverage of the genes comes from
+    /// '--coverage-from'.
+    #[arg(long, requires_all = ["genes", "coverage_from"])]
+    prokaryote: bool,
+
+    /// Gene annotation (GTF with gene or CDS records) for the prokaryotic mode.
+    #[arg(long, value_name = "FILE")]
+    genes: Option<PathBuf>,
+
+    /// Prokaryotic mode: minimum fraction of a gene (CDS) covered by the exons of a transcript.
+    #[arg(long, default_value_t = 0.5)]
+    min_gene_fraction: f32,
+
+    /// Prokaryotic mode: maximum distance (bp) between consecutive genes of an operon.
+    #[arg(long, default_value_t = 300, allow_negative_numbers = true)]
+    max_intergenic: i64,
+
+    #[command(flatten)]
+    output: OutputArgs,
 
-    /// Output directory.
+    /// Policy to resolve OpGs claimed by more than one container.
+    #[arg(long, value_enum, default_value_t = ConflictPolicy::First)]
+    conflict_policy: ConflictPolicy,
+
+    /// Method to select non-overlapping OpGs inside containers and OPRNs.
+    #[arg(long, value_enum, default_value_t = Selection::Greedy)]
+    selection: Selection,
+
+    /// Objective maximised by '--selection optimal'.
+    #[arg(long, value_enum, default_value_t = Objective::Fpkm)]
+    objective: Objective,
+
+    /// Sweep mode: comma-separated coverage threshold multipliers to evaluate.
+    #[arg(long, value_delimiter = ',')]
+    sweep_threshold: Vec<f32>,
+
+    /// Sweep mode: comma-separated monoexonic coverage threshold multipliers to evaluate.
+    #[arg(long, value_delimiter = ',')]
+    sweep_monoexonic_t: Vec<f32>,
+
+    /// Sweep mode: comma-separated minimum exonic overlap percentages to evaluate.
+    #[arg(long, value_delimiter = ',')]
+    sweep_min_overlap: Vec<f32>,
+
+    /// Sweep mode: comma-separated minimum bp overlaps to evaluate.
+    #[arg(long, value_delimiter = ',')]
+    sweep_bp_overlap: Vec<u64>,
+
+    /// Sweep mode: also write the operons found by every parameter combination.
+    #[arg(long)]
+    sweep_sets: bool,
+
+    /// Write the decision trace of every candidate container.
+    #[arg(long)]
+    explain: bool,
+
+    /// Write the decision trace of this transcript only (can be repeated).
+    #[arg(long = "explain-transcript", value_name = "ID")]
+    explain_transcripts: Vec<String>,
+
+    /// Extent of the synthetic operon features.
+    #[arg(long, value_enum, default_value_t = OperonSpan::Opgs)]
+    operon_span: OperonSpan,
+
+    /// Expression value used for the polarity of the OPRNs.
+    #[arg(long, value_enum, default_value_t = Measure::Fpkm)]
+    polarity_measure: Measure,
+
+    /// Genome FASTA (indexed with 'samtools faidx') to extract the sequences of the outputs.
+    #[arg(long)]
+    genome: Option<PathBuf>,
+
+    /// Splice junctions (STAR 'SJ.out.tab' or regtools BED12) to count the reads bridging consecutive OpGs.
+    #[arg(long)]
+    junctions: Option<PathBuf>,
+
+    /// Keep only the OPRNs whose consecutive OpGs are all bridged by at least this many junction reads.
+    #[arg(long, requires = "junctions")]
+    min_bridging_reads: Option<u64>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ExportArgs {
+    /// Path to the input GTF file of the previous run.
     #[arg(short, long)]
-    outdir: Option<String>,
+    file: PathBuf,
 
-    /// Log file path.
+    /// Operons table ('_operons_found_' TSV) of the previous run.
     #[arg(long)]
-    log: Option<String>,
+    operons: PathBuf,
+
+    /// Coverage threshold multiplier of the previous run, used in the output file names.
+    #[arg(short, long, default_value_t = 1.0)]
+    threshold: f32,
+
+    /// Coverage threshold multiplier for monoexonic transcripts, used to build 'opCLEAN'.
+    #[arg(long, default_value_t = 10.0)]
+    monoexonic_t: f32,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    /// Extent of the synthetic operon features.
+    #[arg(long, value_enum, default_value_t = OperonSpan::Opgs)]
+    operon_span: OperonSpan,
+
+    /// Expression value used for the polarity of the OPRNs.
+    #[arg(long, value_enum, default_value_t = Measure::Fpkm)]
+    polarity_measure: Measure,
+
+    /// Genome FASTA (indexed with 'samtools faidx') to extract the sequences of the outputs.
+    #[arg(long)]
+    genome: Option<PathBuf>,
+
+    /// Log and skip malformed GTF records instead of stopping at the first one.
+    #[arg(long)]
+    skip_invalid: bool,
 }
 
-#[derive(Debug, Clone)]
-struct Transcript {
-    id: String,
-    gene_id: String,
-    chrom: String,
-    start: u64,
-    end: u64,
-    strand: String,
-    coverage: f32,
-    fpkm_val: f32,
-    exons: Vec<(u64, u64)>,
-    raw_lines: Vec<String>,
+#[derive(clap::Args, Debug)]
+struct PredictArgs {
+    /// Gene annotation (GTF); each gene spans its CDS records or, without CDS, its other records.
+    #[arg(short, long)]
+    file: PathBuf,
+
+    /// Largest distance (bp) between consecutive genes of an operon; overlapping genes are at a
+    /// negative distance.
+    #[arg(long, default_value_t = 300, allow_negative_numbers = true)]
+    max_intergenic: i64,
+
+    /// Minimum number of genes of a predicted operon.
+    #[arg(long, default_value_t = 2)]
+    min_genes: usize,
+
+    /// Operons table ('_operons_found_' TSV) of an expression-based run to compare the predictions with.
+    #[arg(long)]
+    calls: Option<PathBuf>,
+
+    /// Input GTF of the run of '--calls', giving the coordinates of OpGs that are not genes of
+    /// the annotation (default mode).
+    #[arg(long, requires = "calls")]
+    calls_gtf: Option<PathBuf>,
+
+    /// Log and skip malformed GTF records instead of stopping at the first one.
+    #[arg(long)]
+    skip_invalid: bool,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    /// Genome FASTA (indexed with 'samtools faidx') to extract the sequences of the outputs.
+    #[arg(long)]
+    genome: Option<PathBuf>,
 }
-fn exons_overlap(t1: &Transcript, t2: &Transcript, min_overlap: f32, bp_overlap: u64) -> bool {
-    let mut count_overlap = 0 ;
-    let mut bp_overlap = bp_overlap ;
-    for (s1, e1) in &t1.exons {
-        for (s2, e2) in &t2.exons {
-            if e1.saturating_sub(*s1) < bp_overlap || e2.saturating_sub(*s2) < bp_overlap {
-                bp_overlap = std::cmp::min(e2.saturating_sub(*s2), e1.saturating_sub(*s1))
-            }
-            let overlap_start = std::cmp::max(*s1, *s2);
-            let overlap_end = std::cmp::min(*e1, *e2);
-            if overlap_end > overlap_start && overlap_end.saturating_sub(overlap_start) >= bp_overlap {
-                count_overlap +=1 ;
-            }
+
+// Writes the container decision traces; restricted to `ids` when given, otherwise
+// every transcript with at least one contained transcript
+fn write_explain(file: &mut dyn Write, detection: &detect::Detection, ids: &[String]) -> anyhow::Result<()> {
+    let join = |ids: &[String]| if ids.is_empty() { ".".to_string() } else { ids.join(",") };
+    writeln!(file, "Transcript\tChrom\tStrand\tStart\tEnd\tCoverage\tExons\tContained\tHigher_cov_isoforms\tSelected_OpGs\tOpG_exons\tOperon\tDecision")?;
+    for trace in &detection.traces {
+        let requested = ids.contains(&trace.container_id);
+        if (ids.is_empty() && trace.contained.is_empty()) || (!ids.is_empty() && !requested) {
+            continue;
         }
+        if requested {
+            log::info!("{}: {} ({} contained, {} higher-coverage isoforms, {} selected OpGs)", trace.container_id, trace.decision, trace.contained.len(), trace.isoforms.len(), trace.selected.len());
+        }
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            trace.container_id,
+            trace.chrom,
+            trace.strand,
+            trace.start,
+            trace.end,
+            trace.coverage,
+            trace.container_exons,
+            join(&trace.contained),
+            join(&trace.isoforms),
+            join(&trace.selected),
+            trace.opg_exons,
+            trace.operon.as_deref().unwrap_or("."),
+            trace.decision,
+        )?;
     }
-    let required_overlaps = (t2.exons.len() as f32 * min_overlap).ceil() as usize;
-    if count_overlap >= required_overlaps {
-        return true ;
+    for id in ids.iter().filter(|id| !detection.traces.iter().any(|t| &t.container_id == *id)) {
+        log::warn!("Transcript {} not found in the input GTF", id);
     }
-    false
+    Ok(())
 }
 
-// Helper to resolve if t1 (OPRN) contains t2 (OpG); t1 less cov than t2
-fn transcripts_inside_op(t1: &Transcript, t2: &Transcript, tolerance: u64, threshold: f32, monoexonic_t: f32, min_overlap: f32, bp_overlap: u64) -> bool {
-    t1.start <= t2.start + tolerance && t2.start + tolerance < t1.end + tolerance 
-    && t1.end + tolerance >= t2.end && t2.end > t1.start
-    && t1.coverage * threshold < t2.coverage
-    && (t2.exons.len() > 1 || (t1.coverage * monoexonic_t < t2.coverage))
-    && exons_overlap(t1, t2, min_overlap, bp_overlap)
-}
-// Helper to resolve if t2 is an isoform of t1; t2 less cov than t1
-fn transcripts_inside(t1: &Transcript, t2: &Transcript, tolerance: u64, threshold: f32, monoexonic_t: f32,) -> bool {
-    t1.start <= t2.start + tolerance && t2.start + tolerance < t1.end + tolerance 
-    && t1.end + tolerance >= t2.end && t2.end > t1.start
-    && t1.coverage > t2.coverage * threshold
-    && (t2.exons.len() > 1 || (t1.coverage > t2.coverage * monoexonic_t ))
+// Output choices shared by 'detect', 'export' and 'predict'
+struct OutputOptions {
+    operon_span: OperonSpan,
+    polarity_measure: Measure,
+    genome: Option<PathBuf>,
+    // Whether the transcripts carry expression values; without them the polarity tables are not
+    // written and the expression columns are left empty
+    expression: bool,
+    // Whether the OpGs carry an FPKM value, which the annotated genes of the prokaryotic mode lack
+    fpkm: bool,
 }
 
-fn transcripts_no_overlap(t1: &Transcript, t2: &Transcript, tolerance: u64) -> bool {
-    t1.start > t2.end.saturating_sub(tolerance)
+// Writes the expression gradient along the OpGs of every OPRN, then its genome-wide summary
+fn write_polarity(out: &OutputPaths, tag: &str, annotation: &Annotation, detection: &Detection, measure: Measure) -> anyhow::Result<()> {
+    let polarities = polarity::polarity(detection, &annotation.transcripts_by_chrom, measure);
+    let polarity_path = format!("{}_polarity_{}.tsv", out.prefix, tag);
+    let mut polarity_file = out.create(polarity_path.clone())?;
+    writeln!(polarity_file, "Operon\tStrand\tOpGs\tExpression\tLog2_slope\tLog2_first_last\tSpearman_rho\tDecreasing_steps\tTrend")?;
+    for p in &polarities {
+        writeln!(
+            polarity_file,
+            "{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{}\t{}/{}\t{}",
+            p.operon_id,
+            gtf::strand_symbol(&p.strand),
+            p.expression.len(),
+            p.expression.iter().map(|e| format!("{:.4}", e)).join(","),
+            p.slope,
+            p.first_last_ratio,
+            p.spearman.map(|r| format!("{:.4}", r)).unwrap_or_else(|| "NA".into()),
+            p.decreasing_steps,
+            p.expression.len() - 1,
+            p.trend()
+        )?;
+    }
+    let mut slopes: Vec<f64> = polarities.iter().map(|p| p.slope).collect();
+    slopes.sort_by(f64::total_cmp);
+    let negative = slopes.iter().filter(|&&s| s < 0.0).count();
+    let positive = slopes.iter().filter(|&&s| s > 0.0).count();
+    let rhos: Vec<f64> = polarities.iter().filter_map(|p| p.spearman).collect();
+    // 'NA' when no OPRN has a value
+    let median = |v: &[f64]| match v.len() {
+        0 => "NA".to_string(),
+        n if n % 2 == 1 => format!("{:.4}", v[n / 2]),
+        n => format!("{:.4}", (v[n / 2 - 1] + v[n / 2]) / 2.0),
+    };
+    let mean = |v: &[f64]| match v.len() {
+        0 => "NA".to_string(),
+        n => format!("{:.4}", v.iter().sum::<f64>() / n as f64),
+    };
+    let summary_path = format!("{}_polarity_summary_{}.tsv", out.prefix, tag);
+    let mut summary_file = out.create(summary_path.clone())?;
+    writeln!(summary_file, "Metric\tValue")?;
+    writeln!(summary_file, "Measure\t{:?}", measure)?;
+    writeln!(summary_file, "OPRNs\t{}", polarities.len())?;
+    for trend in ["decreasing", "increasing", "mixed", "flat"] {
+        writeln!(summary_file, "Trend_{}\t{}", trend, polarities.iter().filter(|p| p.trend() == trend).count())?;
+    }
+    writeln!(summary_file, "Negative_slope\t{}", negative)?;
+    writeln!(summary_file, "Positive_slope\t{}", positive)?;
+    writeln!(summary_file, "Median_log2_slope\t{}", median(&slopes))?;
+    writeln!(summary_file, "Mean_log2_slope\t{}", mean(&slopes))?;
+    writeln!(summary_file, "Mean_spearman_rho\t{}", mean(&rhos))?;
+    writeln!(summary_file, "Sign_test_p_value\t{:.4e}", polarity::sign_test(negative, negative + positive))?;
+    log::info!(
+        "Polarity ({:?}): {} of {} OPRNs with a negative slope, median log2 slope {}; written to {} and {}",
+        measure,
+        negative,
+        polarities.len(),
+        median(&slopes),
+        polarity_path,
+        summary_path
+    );
+    Ok(())
 }
 
-fn operontrans_overlap(t1: &Transcript, t2: &Transcript, tolerance: u64) -> bool {
-    t1.start <= t2.end.saturating_sub(tolerance) && t1.end >= t2.start + tolerance
-}
+// Writes the operons table and the GTF outputs of a detection
+fn write_outputs(out: &OutputPaths, tag: &str, annotation: &Annotation, detection: &Detection, options: &OutputOptions) -> anyhow::Result<()> {
+    let mut tsv_path = out.prefix.clone();
+    tsv_path.push_str(&format!("_operons_found_{}.tsv", tag));
+    let mut tsv_file = out.create(tsv_path.clone())?;
+    writeln!(tsv_file, "Operon\tOperonTrans\tContained_transcript\tOpG_rank\tOpG_position")?;
+    for (operon_id, operon, inner_trans) in &detection.operons {
+        let (rank, size) = detection.opg_rank(operon_id, inner_trans).unwrap_or((0, 0));
+        writeln!(tsv_file, "{}\t{}\t{}\t{}\t{}", operon_id, operon, inner_trans, rank, detect::position_label(rank, size))?;
+    }
+    log::info!("Output written to {}", tsv_path);
 
-fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    gtf::write_gtf(&mut out.create(format!("{}_Operons_{}.gtf", out.prefix, tag))?, &detection.operon_ids, &annotation.raw_lines_by_id)?;
+    // Rank and position of every OpG in its OPRNs (comma-separated when it is in several)
+    let mut opg_attributes: HashMap<String, Vec<(&str, String)>> = HashMap::new();
+    for opg_id in &detection.gene_ids {
+        let positions: Vec<(usize, usize)> = detection.operons
+            .iter()
+            .filter(|(_, _, inner_trans)| inner_trans == opg_id)
+            .map(|(operon_id, _, _)| operon_id)
+            .unique()
+            .filter_map(|operon_id| detection.opg_rank(operon_id, opg_id))
+            .collect();
+        opg_attributes.insert(opg_id.clone(), vec![
+            ("opg_rank", positions.iter().map(|(rank, _)| rank.to_string()).join(",")),
+            ("opg_position", positions.iter().map(|&(rank, size)| detect::position_label(rank, size)).join(",")),
+        ]);
+    }
+    gtf::write_gtf_with_attributes(
+        &mut out.create(format!("{}_OperonGenes_{}.gtf", out.prefix, tag))?,
+        &detection.gene_ids,
+        &annotation.raw_lines_by_id,
+        &opg_attributes,
+    )?;
 
-    let gtf_path = &args.file;
-    let threshold = args.threshold;
-    let monoexonic_t = args.monoexonic_t;
-    let min_overlap = args.min_overlap;
-    let min_bp_overlap = args.bp_overlap;
-    let out_prefix = args.prefix.clone().unwrap_or_else(|| {
-        gtf_path.file_stem().unwrap().to_string_lossy().to_string()
-    });
-    let outdir_opt = args.outdir.clone();
-
-    // Helper to resolve final GTF output path respecting optional outdir
-    let gtf_out_path = |name: String| -> String {
-        if let Some(ref outdir) = outdir_opt {
-            Path::new(outdir).join(name).to_string_lossy().to_string()
-        } else {
-            name
+    let mut all_gids = HashSet::new();
+    for transcripts in annotation.transcripts_by_chrom.values() {
+        for trans in transcripts {
+            if detection.all_gene_gids.contains(&trans.gene_id) || detection.all_oprn_gids.contains(&trans.gene_id) {
+                all_gids.insert(trans.id.clone());
+            }
         }
-    };
-
-    if let Some(ref outdir) = outdir_opt {
-        std::fs::create_dir_all(outdir)?;
     }
 
-    let log_file = args.log.clone().unwrap_or_else(|| gtf_out_path(format!("{}_gamba.log", out_prefix)));
+    let all_genes_ids: HashSet<String> = annotation.raw_lines_by_id
+        .keys()
+        .filter(|id| !detection.operon_ids.contains(*id) && all_gids.contains(*id))
+        .cloned()
+        .collect();
+    gtf::write_gtf(&mut out.create(format!("{}_OperonGenesALL_{}.gtf", out.prefix, tag))?, &all_genes_ids, &annotation.raw_lines_by_id)?;
 
-    Ftail::new()
-        .datetime_format("%Y-%m-%d %H:%M:%S")
-        .console(LevelFilter::Off)
-        .single_file(Path::new(&log_file), false, LevelFilter::Info)
-        .init()?;
+    let clean_ids: HashSet<String> = annotation.raw_lines_by_id
+        .keys()
+        .filter(|id| !detection.operon_ids.contains(*id) && !all_gids.contains(*id) && detection.good_cov_ids.contains(*id))
+        .cloned()
+        .collect();
+    gtf::write_gtf(&mut out.create(format!("{}_opCLEAN_{}.gtf", out.prefix, tag))?, &clean_ids, &annotation.raw_lines_by_id)?;
 
-    let mut reader = gtf::io::Reader::new(BufReader::new(File::open(gtf_path)?));
-    let mut transcripts_by_chrom: BTreeMap<String, Vec<Transcript>> = BTreeMap::new();
-    let mut exons_by_transcript: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
-    let mut raw_lines_by_id: HashMap<String, Vec<String>> = HashMap::new();
-    
-    for result in reader.record_bufs() {
-        let record = result?;
-        let tid = record.attributes().get("transcript_id".as_ref()).map(|v| v.as_string().unwrap().to_string()).unwrap_or("NA".into()).clone();
-
-        if record.ty() == "transcript" {
-            let gid = record.attributes().get("gene_id".as_ref()).map(|v| v.as_string().unwrap().to_string()).unwrap_or("NA".into()).clone();
-            let cov = record.attributes()
-                .get("cov".as_ref())
-                .and_then(|v| v.as_string())
-                .and_then(|s| s.to_string().parse::<f32>().ok())
-                .unwrap_or(0.0);
-            let fpkm = record.attributes()
-                .get("FPKM".as_ref())
-                .and_then(|v| v.as_string())
-                .and_then(|s| s.to_string().parse::<f32>().ok())
-                .unwrap_or(0.0);
-            let transcript = Transcript {
-                id: tid.clone(),
-                gene_id: gid,
-                chrom: record.reference_sequence_name().to_string(),
-                start: record.start().get() as u64,
-                end: record.end().get() as u64,
-                strand: format!("{:?}", record.strand()),
-                coverage: cov,
-                fpkm_val: fpkm,
-                exons: Vec::new(),
-                raw_lines: vec![format!("{:?}", record)],
-            };
-            transcripts_by_chrom.entry(transcript.chrom.clone()).or_default().push(transcript);
-            let buf = Vec::new();
-            let mut writer = noodles::gff::io::Writer::new(buf);
-            writer.write_record(&record).expect("Unable to write GFF record");
-            raw_lines_by_id.entry(tid.clone()).or_default().push(String::from_utf8(writer.into_inner())?);
-        } else if record.ty() == "exon" {
-            let start = record.start().get();
-            let end = record.end().get();
-            exons_by_transcript.entry(tid.clone()).or_default().push((start as u64, end as u64));
-            let buf = Vec::new();
-            let mut writer = noodles::gff::io::Writer::new(buf);
-            writer.write_record(&record).expect("Unable to write GFF record");
-            raw_lines_by_id.entry(tid.clone()).or_default().push(String::from_utf8(writer.into_inner())?);
+    // Complete annotation: the clean transcripts plus the genes of the OPRNs in place of their containers
+    let genes = detect::operon_genes(detection, &annotation.transcripts_by_chrom);
+    let mut operons_by_gene: HashMap<&str, Vec<&str>> = HashMap::new();
+    for gene in &genes {
+        operons_by_gene.entry(gene.gene_id.as_str()).or_default().push(gene.operon_id.as_str());
+    }
+    let mut operon_attributes: HashMap<String, Vec<(&str, String)>> = HashMap::new();
+    for trans in annotation.transcripts_by_chrom.values().flatten() {
+        if let Some(operon_ids) = operons_by_gene.get(trans.gene_id.as_str()) {
+            let mut attributes = vec![("operon_id", operon_ids.iter().unique().join(","))];
+            attributes.extend(opg_attributes.get(&trans.id).cloned().unwrap_or_default());
+            operon_attributes.insert(trans.id.clone(), attributes);
         }
     }
+    let resolved_ids: HashSet<String> = clean_ids.union(&all_genes_ids).cloned().collect();
+    gtf::write_gtf_with_attributes(
+        &mut out.create(format!("{}_resolved_{}.gtf", out.prefix, tag))?,
+        &resolved_ids,
+        &annotation.raw_lines_by_id,
+        &operon_attributes,
+    )?;
 
-    for transcripts in transcripts_by_chrom.values_mut() {
-        for transcript in transcripts.iter_mut() {
-            if let Some(exons) = exons_by_transcript.get(&transcript.id) {
-                transcript.exons = exons.clone();
-            }
-            if let Some(lines) = raw_lines_by_id.get(&transcript.id) {
-                transcript.raw_lines = lines.clone();
-            }
+    log::info!("GTF files written successfully.");
+
+    let genes_path = format!("{}_operon_genes_{}.tsv", out.prefix, tag);
+    let mut genes_file = out.create(genes_path.clone())?;
+    writeln!(genes_file, "Operon\tGene_rank\tPosition\tGene_id\tChrom\tStart\tEnd\tStrand\tIsoforms\tCoverage\tFPKM")?;
+    let expression = |value: f32, known: bool| if known { format!("{:.4}", value) } else { "NA".to_string() };
+    for operon_genes in genes.chunk_by(|g1, g2| g1.operon_id == g2.operon_id) {
+        for (rank, gene) in operon_genes.iter().enumerate() {
+            writeln!(
+                genes_file,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                gene.operon_id,
+                rank + 1,
+                detect::position_label(rank + 1, operon_genes.len()),
+                gene.gene_id,
+                gene.chrom,
+                gene.start,
+                gene.end,
+                gtf::strand_symbol(&gene.strand),
+                gene.isoforms.join(","),
+                expression(gene.coverage, options.expression),
+                expression(gene.fpkm, options.expression && options.fpkm)
+            )?;
         }
     }
+    log::info!("Gene-level operon table written to {}", genes_path);
+
+    if options.expression {
+        write_polarity(out, tag, annotation, detection, options.polarity_measure)?;
+    }
 
-    let mut operon_to_genes: Vec<(GeneId, Transcript, &Transcript)> = Vec::new();
-    let mut good_cov_ids = HashSet::new();
-    for (chrom, transcripts) in &transcripts_by_chrom {
-        log::info!("Processing chromosome {} ({} transcripts)...", chrom, transcripts.len());
-        for container in transcripts {
-            let mut contained = Vec::new();
-            let mut counter=0;
-            if (container.exons.len() > 1 ) || (container.coverage >= monoexonic_t ){
-                good_cov_ids.insert(container.id.clone());
+    let features = detect::operon_features(detection, &genes, &annotation.transcripts_by_chrom, options.operon_span);
+    let mut features_gtf = out.create(format!("{}_operon_features_{}.gtf", out.prefix, tag))?;
+    let mut features_gff3 = out.create_gff3(format!("{}_operon_features_{}.gff3", out.prefix, tag))?;
+    for feature in &features {
+        let columns = format!(
+            "{}\tgamba\toperon\t{}\t{}\t{}\t{}\t.",
+            feature.chrom,
+            feature.start,
+            feature.end,
+            if options.expression { format!("{:.4}", feature.score) } else { ".".to_string() },
+            gtf::strand_symbol(&feature.strand)
+        );
+        writeln!(
+            features_gtf,
+            "{}\tgene_id \"{}\"; transcript_id \"{}\"; operon_id \"{}\"; genes \"{}\"; containers \"{}\"; size \"{}\";",
+            columns,
+            feature.operon_id,
+            feature.operon_id,
+            feature.operon_id,
+            feature.genes.join(","),
+            feature.containers.join(","),
+            feature.size
+        )?;
+        writeln!(
+            features_gff3,
+            "{}\tID={};genes={};containers={};size={}",
+            columns,
+            feature.operon_id,
+            feature.genes.join(","),
+            feature.containers.join(","),
+            feature.size
+        )?;
+    }
+    log::info!("Operon features ({:?} span) written to {}_operon_features_{}.gtf/.gff3", options.operon_span, out.prefix, tag);
+
+    let mut genome = options.genome.as_deref().map(Genome::open).transpose()?;
+    let regions = detect::intercistronic_regions(detection, &annotation.transcripts_by_chrom);
+    let mut bed = out.create(format!("{}_intercistronic_{}.bed", out.prefix, tag))?;
+    let mut fasta = match genome {
+        Some(_) => Some(out.create_fasta(format!("{}_intercistronic_{}.fa", out.prefix, tag))?),
+        None => None,
+    };
+    for region in &regions {
+        let name = format!("{}_ICR{}", region.operon_id, region.rank);
+        let strand = gtf::strand_symbol(&region.strand);
+        writeln!(
+            bed,
+            "{}\t{}\t{}\t{}\t0\t{}\t{}\t{}\t{}",
+            region.chrom,
+            region.start - 1,
+            region.end,
+            name,
+            strand,
+            region.operon_id,
+            region.upstream,
+            region.downstream
+        )?;
+        if let (Some(genome), Some(fasta)) = (genome.as_mut(), fasta.as_mut()) {
+            let mut seq = genome.fetch(&region.chrom, region.start, region.end)?;
+            if strand == "-" {
+                seq = sequence::reverse_complement(&seq);
             }
-            for inner in transcripts {
-                if container.id == inner.id || container.strand != inner.strand {
-                    continue;
-                }
-                if transcripts_inside_op(container,inner, 250, threshold, monoexonic_t , min_overlap, min_bp_overlap) {
-                    contained.push(inner);
-                }
-                if transcripts_inside(inner,container, 250, threshold, monoexonic_t) {
-                    counter += 1;
+            let header = format!("{} {}:{}-{}({}) {}>{}", name, region.chrom, region.start, region.end, strand, region.upstream, region.downstream);
+            sequence::write_fasta(fasta, &header, &seq)?;
+        }
+    }
+    log::info!("Intercistronic regions written to {}_intercistronic_{}.bed ({} regions)", out.prefix, tag, regions.len());
+
+    // Spliced sequences of the containers and OpGs, with the OPRNs of each one
+    if let Some(genome) = genome.as_mut() {
+        let by_id = annotation.by_id();
+        let mut containers: Vec<(&str, Vec<&str>)> = Vec::new();
+        let mut opgs: Vec<(&str, Vec<&str>)> = Vec::new();
+        for (operon_id, operon, inner_trans) in &detection.operons {
+            for (list, id) in [(&mut containers, operon), (&mut opgs, inner_trans)] {
+                match list.iter_mut().find(|(listed, _)| listed == id) {
+                    Some((_, operon_ids)) if !operon_ids.contains(&operon_id.as_str()) => operon_ids.push(operon_id),
+                    Some(_) => {}
+                    None => list.push((id, vec![operon_id])),
                 }
             }
-            if contained.len() >= 2 && counter == 0 {
-                let mut non_overlapping = Vec::new();
-                contained.sort_by(|&e1 , &e2| {
-                    let id1 = e1.start;
-                    let id2 = e2.start;
-                    if id1 > id2 { Ordering::Greater } else if id1 < id2 { Ordering::Less } else { Ordering::Equal }
-                });
-                for gene in contained {
-                    if non_overlapping.last().map_or(true, |last: &&Transcript| transcripts_no_overlap(gene,last,50) ) {
-                        non_overlapping.push(gene);
-                    } else {
-                        let last = *non_overlapping.last().unwrap();
-                        if gene.exons.len() > 1 {
-                            if last.exons.len() == 1 || gene.fpkm_val > last.fpkm_val || (gene.fpkm_val == last.fpkm_val && gene.exons.len() > last.exons.len()) {
-                                non_overlapping.pop();
-                                non_overlapping.push(gene);
-                            }
-                        }
-                        if gene.exons.len() == 1 && last.exons.len() == 1 {
-                            if gene.fpkm_val > last.fpkm_val {
-                                non_overlapping.pop();
-                                non_overlapping.push(gene);
-                            }
-                        }
-                    }
-                }
-
-                if non_overlapping.len() >= 2 {
-                    let mut exon_num=0;
-                    for gene in non_overlapping.clone() { exon_num += gene.exons.len() }
-                    if exon_num > container.exons.len() {
-                        for gene in non_overlapping {
-                            operon_to_genes.push((container.gene_id.clone(), container.clone(), gene));
-                        }
-                    }
+        }
+        for (kind, list) in [("containers", &containers), ("opgs", &opgs)] {
+            let path = format!("{}_{}_{}.fa", out.prefix, kind, tag);
+            let mut fasta = out.create_fasta(path.clone())?;
+            for (id, operon_ids) in list {
+                let Some(transcript) = by_id.get(id) else { continue };
+                let mut gene = format!("gene={}", transcript.gene_id);
+                if let Some(attributes) = opg_attributes.get(*id).filter(|_| kind == "opgs") {
+                    gene.push_str(&format!(" rank={} position={}", attributes[0].1, attributes[1].1));
                 }
+                let header = format!(
+                    "{} {} {} {}:{}-{}({})",
+                    id,
+                    operon_ids.join(","),
+                    gene,
+                    transcript.chrom,
+                    transcript.start,
+                    transcript.end,
+                    gtf::strand_symbol(&transcript.strand)
+                );
+                sequence::write_fasta(&mut fasta, &header, &genome.spliced(transcript)?)?;
             }
+            log::info!("Spliced sequences of {} {} written to {}", list.len(), kind, path);
         }
     }
+    
+    log::info!("Total number of OPRNs found: {}", &detection.operon_gene_map.keys().len());
+    log::info!("Total number of OpGs found: {}", detection.operons.len());
+
+    // Summary
+    let summary = detect::size_summary(&detection.operon_gene_map);
+
+    log::info!("Summary of operons by gene number:");
 
-    let mut chr_to_operons: HashMap<(String, String), Vec<(String, &Transcript, &&Transcript)>> = HashMap::new();
-    for (op_gene_id, op_id, trans_id) in operon_to_genes.iter() {
-        chr_to_operons.entry((op_id.chrom.clone(),op_id.strand.clone())).or_default().push((op_gene_id.clone(), op_id, trans_id));
+    for (category, count) in &summary {
+        log::info!("{}: {}", category, count);
     }
 
-    let mut overlapping: Vec<(String, Transcript, &Transcript)> = Vec::new();
-    let mut seen_transcripts = HashSet::new();
-    let mut counter = 1;
-    for ((_chrom, _strand), mut op_list) in chr_to_operons {
-        op_list.sort_by_key(|(_, p, _)| p.start);
-        for (_ , current_op, inner_trans) in op_list {
-            if seen_transcripts.contains(&inner_trans.id) {
-                continue;
-            }
-            if let Some((_, last, _ ) ) = overlapping.last() {
-                if operontrans_overlap(current_op, last, 250) {
-                    overlapping.push((format!("OPRN.{}", counter), current_op.clone(), &inner_trans));
-                } else {
-                    counter += 1;
-                    overlapping.push((format!("OPRN.{}", counter), current_op.clone(), &inner_trans));
-                }
-            } else {
-                overlapping.push((format!("OPRN.{}", counter), current_op.clone(), &inner_trans));
-            }
-            seen_transcripts.insert(inner_trans.id.clone());
-        }
+    Ok(())
+}
+
+fn run_detect(args: DetectArgs, matches: &ArgMatches) -> anyhow::Result<()> {
+    let gtf_path = &args.file;
+    let params = Params {
+        threshold: args.threshold,
+        monoexonic_t: args.monoexonic_t,
+        min_overlap: args.min_overlap,
+        bp_overlap: args.bp_overlap,
+        tolerance: args.tolerance,
+        opg_tolerance: args.opg_tolerance,
+        conflict_policy: args.conflict_policy,
+        selection: args.selection,
+        objective: args.objective,
+    };
+    let config_file = args.config.as_deref().map(config::ParamsFile::read).transpose()?;
+    let mut out = OutputPaths::new(gtf_path, &args.output)?;
+    out.init_log()?;
+
+    if let Some(ref path) = args.config {
+        log::info!("Reading parameters from {}", path.display());
     }
+    if let Some(preset) = args.preset {
+        log::info!("Using preset {:?}", preset);
+    }
+    let params = config::resolve(params, matches, config_file.as_ref(), args.preset);
+    let prokaryote_params = args.prokaryote.then_some(prokaryote::ProkaryoteParams {
+        min_gene_fraction: args.min_gene_fraction,
+        max_intergenic: args.max_intergenic,
+    });
+    let mut entries = params.entries();
+    if let Some(ref p) = prokaryote_params {
+        // The exon-based criteria do not apply to the genes
+        entries.retain(|(name, _)| !["monoexonic_t", "min_overlap", "bp_overlap"].contains(name));
+        entries.extend(p.entries());
+    }
+    out.provenance = Some(Provenance::new(gtf_path, &entries)?);
+    let threshold = params.threshold;
 
-    let mut operon_to_trans: HashMap<String, Vec<(Transcript,&Transcript)>> = HashMap::new();
-    for (op_id, operon, inner_trans) in overlapping.iter() {
-        operon_to_trans.entry(op_id.clone()).or_default().push((operon.clone(), inner_trans));
+    let mut region_filter = RegionFilter {
+        regions: args.regions.clone(),
+        chroms: args.chroms.iter().cloned().collect(),
+    };
+    if let Some(ref bed) = args.regions_bed {
+        region_filter.regions.extend(regions::read_bed(bed)?);
     }
-    let mut operon_to_trans_def: Vec<(String, String, String)> = Vec::new();
-    let mut operon_ids = HashSet::new();
-    let mut gene_ids = HashSet::new();
-    let mut all_gene_gids = HashSet::new();
-    let mut all_oprn_gids = HashSet::new();
-    let mut operon_gene_map: HashMap<String, Vec<String>> = HashMap::new();
-
-    for (operon_id, transcripts_list) in operon_to_trans {
-        let mut non_overlapping_def: Vec<&Transcript> = Vec::new();
-        let transcripts_list_ordered = transcripts_list.iter().sorted_by(|(_, e1 ), (_, e2)| {
-            let id1 = e1.start;
-            let id2 = e2.start;
-            let id1_strand = &e1.strand;
-            let id2_strand = &e2.strand;
-            if id1_strand == id2_strand && id1 > id2 { Ordering::Greater } else if id1_strand == id2_strand && id1 < id2 { Ordering::Less } else { Ordering::Equal }
-        });
-        for (_, gene) in transcripts_list_ordered {
-            if non_overlapping_def.last().map_or(true, |last: &&Transcript| transcripts_no_overlap(gene,last,50) ) {
-                non_overlapping_def.push(gene);
-            } else if gene.fpkm_val > non_overlapping_def.last().unwrap().fpkm_val {
-                non_overlapping_def.pop();
-                non_overlapping_def.push(gene);
-            }
-        } 
-        
-        if non_overlapping_def.len() >= 2 {
-            for (operon, gene) in transcripts_list {
-                if non_overlapping_def.iter().any(|&i| i.id == gene.id ) {
-                    operon_to_trans_def.push((operon_id.clone(), operon.id.clone(), gene.id.clone()));
-                    operon_ids.insert(operon.id.clone());
-                    gene_ids.insert(gene.id.clone());
-                    all_gene_gids.insert(gene.gene_id.clone());
-                    all_oprn_gids.insert(operon.gene_id.clone());
-                    operon_gene_map.entry(operon_id.clone()).or_default().push(gene.id.clone());
-                }
-            }
+    let mut annotation = match gtf::find_index(gtf_path) {
+        Some(index_path) if !region_filter.is_empty() => {
+            log::info!("Querying {} with index {}", gtf_path.display(), index_path.display());
+            region_filter.read_indexed(gtf_path, &index_path, args.skip_invalid)?
         }
+        _ => gtf::read_annotation(gtf_path, args.skip_invalid)?,
+    };
+    if !region_filter.is_empty() {
+        region_filter.apply(&mut annotation);
+    }
+    if let Some(ref path) = args.coverage_from {
+        coverage::fill_coverage(&mut annotation, path)?;
+    }
+    if let Some(ref path) = args.expression {
+        expression::fill_expression(&mut annotation, path, &out)?;
     }
 
-    let mut tsv_path = out_prefix.clone();
-    tsv_path.push_str(&format!("_operons_found_t{:.1}.tsv", threshold));
-    let mut tsv_file = BufWriter::new(File::create(&gtf_out_path(tsv_path.clone()))?);
-    writeln!(tsv_file, "Operon\tOperonTrans\tContained_transcript")?;
-    operon_to_trans_def.sort_by(|(_,e1,_) , (_, e2, _)| {
-            let e1_els = e1.split(".").collect::<Vec<_>>();
-            let e2_els = e2.split(".").collect::<Vec<_>>();
-            let id1 = format!("{}.{}", e1_els[1], e1_els[2]).parse::<f32>().unwrap();
-            let id2 = format!("{}.{}", e2_els[1], e2_els[2]).parse::<f32>().unwrap();
-            if id1 > id2 { Ordering::Greater } else if id1 < id2 { Ordering::Less } else { Ordering::Equal }
-        });
-    for (operon_id, operon, inner_trans) in &operon_to_trans_def {
-        writeln!(tsv_file, "{}\t{}\t{}", operon_id, operon, inner_trans)?;
+    let grid = SweepGrid {
+        thresholds: args.sweep_threshold.clone(),
+        monoexonic_ts: args.sweep_monoexonic_t.clone(),
+        min_overlaps: args.sweep_min_overlap.clone(),
+        bp_overlaps: args.sweep_bp_overlap.clone(),
+    };
+    if !grid.is_empty() {
+        if prokaryote_params.is_some() {
+            anyhow::bail!("parameter sweeps are not available in prokaryotic mode");
+        }
+        return sweep::run_sweep(&annotation, &params, &grid, &out, args.sweep_sets);
     }
-    log::info!("Output written to {}", tsv_path);
 
-    let write_gtf = |filename: &str, ids: &HashSet<String>| -> anyhow::Result<()> {
-        let mut file = BufWriter::new(File::create(filename)?);
-        let mut ids_ordered = Vec::from_iter(ids);
-        ids_ordered.sort_by(|e1, e2| {
-            let e1_els = e1.split(".").collect::<Vec<_>>();
-            let e2_els = e2.split(".").collect::<Vec<_>>();
-            let id1 = e1_els[1].parse::<u32>().unwrap();
-            let id2 = e2_els[1].parse::<u32>().unwrap();
-            if id1 > id2 { Ordering::Greater } else if id1 < id2 { Ordering::Less } else { Ordering::Equal }
-        });
-
-        for id in ids_ordered {
-            if let Some(lines) = raw_lines_by_id.get(id) {
-                for line in lines {
-                    writeln!(file, "{}", line.replace("\n", ";").replace("=", " \"").replace(";", "\";"))?;
-                }
+    let mut detection = match (&prokaryote_params, &args.genes) {
+        (Some(prokaryote_params), Some(genes_path)) => {
+            let mut genes = prokaryote::read_genes(genes_path, args.skip_invalid)?;
+            // '--prokaryote' requires '--coverage-from'
+            if let Some(ref path) = args.coverage_from {
+                coverage::fill_coverage(&mut genes, path)?;
             }
+            log::info!("Prokaryotic mode: {} genes read from {}", genes.transcripts_by_chrom.values().map(|g| g.len()).sum::<usize>(), genes_path.display());
+            let detection = prokaryote::detect_operons(&annotation.transcripts_by_chrom, &genes, &params, prokaryote_params);
+            // The genes are the OpGs of the outputs
+            for (chrom, chrom_genes) in genes.transcripts_by_chrom {
+                annotation.transcripts_by_chrom.entry(chrom).or_default().extend(chrom_genes);
+            }
+            annotation.raw_lines_by_id.extend(genes.raw_lines_by_id);
+            detection
         }
-        Ok(())
+        _ => detect::detect_operons(&annotation.transcripts_by_chrom, &params),
     };
-
-    write_gtf(&gtf_out_path(format!("{}_Operons_t{:.1}.gtf", out_prefix, threshold)), &operon_ids)?;
-    write_gtf(&gtf_out_path(format!("{}_OperonGenes_t{:.1}.gtf", out_prefix, threshold)), &gene_ids)?;
-
-    let mut all_gids = HashSet::new();
-    for (_, transcripts) in &transcripts_by_chrom {
-        for trans in transcripts {
-            if all_gene_gids.contains(&trans.gene_id) || all_oprn_gids.contains(&trans.gene_id) {
-                all_gids.insert(trans.id.clone());
+    if let Some(ref path) = args.junctions {
+        let junctions = junctions::read_junctions(path)?;
+        log::info!("Read {} junctions from {}", junctions.values().map(|j| j.len()).sum::<usize>(), path.display());
+        let support_path = format!("{}_junction_support_t{:.1}.tsv", out.prefix, threshold);
+        let mut support_file = out.create(support_path.clone())?;
+        writeln!(support_file, "Operon\tUpstream_OpG\tDownstream_OpG\tUpstream_gene\tDownstream_gene\tJunctions\tReads")?;
+        let mut supported = HashSet::new();
+        for (operon_id, opgs) in detect::opgs_by_operon(&detection, &annotation.transcripts_by_chrom) {
+            let mut min_reads = u64::MAX;
+            for pair in opgs.windows(2) {
+                let (count, reads) = junctions::bridging_support(&junctions, pair[0], pair[1]);
+                min_reads = min_reads.min(reads);
+                writeln!(support_file, "{}\t{}\t{}\t{}\t{}\t{}\t{}", operon_id, pair[0].id, pair[1].id, pair[0].gene_id, pair[1].gene_id, count, reads)?;
             }
+            if args.min_bridging_reads.is_none_or(|min| min_reads >= min) {
+                supported.insert(operon_id);
+            }
+        }
+        log::info!("Junction support written to {}", support_path);
+        if let Some(min) = args.min_bridging_reads {
+            log::info!(
+                "OPRNs with every OpG pair bridged by at least {} reads: {} of {}",
+                min,
+                supported.len(),
+                detection.operon_gene_map.len()
+            );
+            detection.retain_operons(&supported, &annotation.transcripts_by_chrom, detect::Decision::JunctionSupport);
         }
     }
-
-    let all_genes_ids: HashSet<String> = raw_lines_by_id
-        .keys()
-        .filter(|id| !operon_ids.contains(*id) && all_gids.contains(*id))
-        .cloned()
-        .collect();
-    write_gtf(&gtf_out_path(format!("{}_OperonGenesALL_t{:.1}.gtf", out_prefix, threshold)), &all_genes_ids)?;
-
-    let clean_ids: HashSet<String> = raw_lines_by_id
-        .keys()
-        .filter(|id| !operon_ids.contains(*id) && !all_gids.contains(*id) && good_cov_ids.contains(*id))
-        .cloned()
-        .collect();
-    write_gtf(&gtf_out_path(format!("{}_opCLEAN_t{:.1}.gtf", out_prefix, threshold)), &clean_ids)?;
-
-    log::info!("GTF files written successfully.");
-    
-    log::info!("Total number of OPRNs found: {}", &operon_gene_map.keys().len());
-    log::info!("Total number of OpGs found: {}", operon_to_trans_def.len());
-
-    // Summary
-    let mut summary = BTreeMap::from([
-        //("1 gene", 0),
-        ("2 genes", 0),
-        ("3 genes", 0),
-        ("4 genes", 0),
-        ("5 genes", 0),
-        (">5 genes", 0),
-    ]);
-
-    for (_operon, genes) in operon_gene_map {
-        match genes.len() {
-            //1 => *summary.get_mut("1 genes").unwrap() += 1,
-            2 => *summary.get_mut("2 genes").unwrap() += 1,
-            3 => *summary.get_mut("3 genes").unwrap() += 1,
-            4 => *summary.get_mut("4 genes").unwrap() += 1,
-            5 => *summary.get_mut("5 genes").unwrap() += 1,
-            n if n > 5 => *summary.get_mut(">5 genes").unwrap() += 1,
-            _ => {},
+    // The annotated genes only have a coverage
+    let polarity_measure = match args.polarity_measure {
+        Measure::Fpkm if prokaryote_params.is_some() => {
+            log::warn!("Genes have no FPKM in prokaryotic mode: polarity is measured on coverage");
+            Measure::Coverage
         }
+        measure => measure,
+    };
+    let options = OutputOptions {
+        operon_span: args.operon_span,
+        polarity_measure,
+        genome: args.genome.clone(),
+        expression: true,
+        fpkm: prokaryote_params.is_none(),
+    };
+    write_outputs(&out, &format!("t{:.1}", threshold), &annotation, &detection, &options)?;
+
+    // Conflicts report: every OpG claimed by more than one container
+    let conflicts_path = format!("{}_conflicts_t{:.1}.tsv", out.prefix, threshold);
+    let mut conflicts_file = out.create(conflicts_path.clone())?;
+    writeln!(conflicts_file, "Contained_transcript\tContainer\tOperon\tContainer_cov\tContainer_length\tStatus")?;
+    for conflict in &detection.conflicts {
+        let status = if conflict.kept { "kept" } else { "dropped" };
+        writeln!(conflicts_file, "{}\t{}\t{}\t{}\t{}\t{}", conflict.inner_id, conflict.container_id, conflict.operon, conflict.container_cov, conflict.container_length, status)?;
+    }
+    let conflict_count = detection.conflicts.iter().map(|c| &c.inner_id).collect::<HashSet<_>>().len();
+    log::info!("OpGs claimed by more than one container: {} (policy: {:?})", conflict_count, params.conflict_policy);
+    log::info!("Conflicts written to {}", conflicts_path);
+
+    if args.explain || !args.explain_transcripts.is_empty() {
+        let explain_path = format!("{}_explain_t{:.1}.tsv", out.prefix, threshold);
+        write_explain(&mut out.create(explain_path.clone())?, &detection, &args.explain_transcripts)?;
+        log::info!("Decision trace written to {}", explain_path);
     }
 
-    //println!("Summary of operons by gene number:");
-    log::info!("Summary of operons by gene number:");
+    Ok(())
+}
 
-    for (category, count) in &summary {
-        //println!("{}: {}", category, count);
-        log::info!("{}: {}", category, count);
-    }
+fn run_export(args: ExportArgs) -> anyhow::Result<()> {
+    let mut out = OutputPaths::new(&args.file, &args.output)?;
+    out.init_log()?;
+    out.provenance = Some(Provenance::new(&args.file, &[
+        ("operons", args.operons.display().to_string()),
+        ("threshold", args.threshold.to_string()),
+        ("monoexonic_t", args.monoexonic_t.to_string()),
+    ])?);
+
+    let annotation = gtf::read_annotation(&args.file, args.skip_invalid)?;
+    let operons = detect::read_operons(&args.operons)?;
+    log::info!("Read {} OpGs from {}", operons.len(), args.operons.display());
+    let detection = Detection::from_operons(operons, &annotation.transcripts_by_chrom, args.monoexonic_t);
+    let options = OutputOptions { operon_span: args.operon_span, polarity_measure: args.polarity_measure, genome: args.genome.clone(), expression: true, fpkm: true };
+    write_outputs(&out, &format!("t{:.1}", args.threshold), &annotation, &detection, &options)
+}
 
+fn run_predict(args: PredictArgs) -> anyhow::Result<()> {
+    let mut out = OutputPaths::new(&args.file, &args.output)?;
+    out.init_log()?;
+    let mut entries = vec![
+        ("max_intergenic", args.max_intergenic.to_string()),
+        ("min_genes", args.min_genes.to_string()),
+    ];
+    entries.extend(args.calls.iter().map(|path| ("calls", path.display().to_string())));
+    out.provenance = Some(Provenance::new(&args.file, &entries)?);
+
+    let genes = prokaryote::read_genes(&args.file, args.skip_invalid)?;
+    log::info!("Read {} genes from {}", genes.transcripts_by_chrom.values().map(|g| g.len()).sum::<usize>(), args.file.display());
+    let detection = predict::predict_operons(&genes, args.max_intergenic, args.min_genes);
+    let options = OutputOptions { operon_span: OperonSpan::Opgs, polarity_measure: Measure::Fpkm, genome: args.genome.clone(), expression: false, fpkm: false };
+    write_outputs(&out, "predicted", &genes, &detection, &options)?;
+
+    if let Some(ref calls_path) = args.calls {
+        let calls = detect::read_operons(calls_path)?;
+        let called_transcripts = match args.calls_gtf {
+            Some(ref path) => gtf::read_annotation(path, args.skip_invalid)?.transcripts_by_chrom,
+            None => Default::default(),
+        };
+        let (matches, supported_calls) = predict::compare_with_calls(&detection, &genes, &calls, &called_transcripts);
+        let comparison_path = format!("{}_predicted_vs_calls.tsv", out.prefix);
+        predict::write_comparison(&mut out.create(comparison_path.clone())?, &matches)?;
+        let count = |status: &str| matches.iter().filter(|m| m.status() == status).count();
+        log::info!("Predicted OPRNs in a called OPRN: {} full, {} partial, {} none", count("full"), count("partial"), count("none"));
+        log::info!(
+            "Called OPRNs with at least two genes in a predicted OPRN: {} of {}",
+            supported_calls,
+            calls.iter().map(|(operon_id, _, _)| operon_id).unique().count()
+        );
+        log::info!("Comparison with {} written to {}", calls_path.display(), comparison_path);
+    }
     Ok(())
 }
+
+fn main() -> anyhow::Result<()> {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches)?;
+    let detect_matches = matches.subcommand_matches("detect").unwrap_or(&matches);
+
+    match cli.command {
+        Some(Command::Detect(args)) => run_detect(*args, detect_matches),
+        Some(Command::Stats(args)) => stats::run(args),
+        Some(Command::Convert(args)) => convert::run(args),
+        Some(Command::Eval(args)) => eval::run(args),
+        Some(Command::Export(args)) => run_export(args),
+        Some(Command::Longread(args)) => longread::run(args),
+        Some(Command::Diff(args)) => diff::run(args),
+        Some(Command::Predict(args)) => run_predict(args),
+        Some(Command::Compare(args)) => compare::run(args),
+        None if matches.args_present() => run_detect(DetectArgs::from_arg_matches(&matches)?, detect_matches),
+        None => {
+            Cli::command().print_help()?;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,140 @@
+// output.rs
+use std::{fs::File, io::{BufReader, BufWriter, Read, Write}, path::Path};
+use ftail::Ftail;
+use log::LevelFilter;
+use sha2::{Digest, Sha256};
+
+/// Comment header written at the top of every output file.
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    lines: Vec<String>,
+}
+
+impl Provenance {
+    /// Records the gamba version, command line, input file and its SHA-256, date and parameters.
+    pub fn new(input: &Path, params: &[(&str, String)]) -> anyhow::Result<Self> {
+        let command = std::env::args()
+            .map(|arg| if arg.contains(char::is_whitespace) { format!("'{}'", arg) } else { arg })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let input_path = std::fs::canonicalize(input).unwrap_or_else(|_| input.to_path_buf());
+
+        let mut lines = vec![
+            format!("#gamba-version {}", env!("CARGO_PKG_VERSION")),
+            format!("#gamba-command {}", command),
+            format!("#gamba-input {}", input_path.display()),
+            format!("#gamba-input-sha256 {}", sha256(input)?),
+            format!("#gamba-date {}", chrono::Local::now().to_rfc3339()),
+        ];
+        for (name, value) in params {
+            lines.push(format!("#gamba-param {}={}", name, value));
+        }
+        Ok(Provenance { lines })
+    }
+
+    pub fn write(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        for line in &self.lines {
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+fn sha256(path: &Path) -> anyhow::Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Output options of the subcommands writing files.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct OutputArgs {
+    /// Output file prefix [default: the input file name].
+    #[arg(short, long)]
+    pub prefix: Option<String>,
+
+    /// Output directory.
+    #[arg(short, long)]
+    pub outdir: Option<String>,
+
+    /// Log file path.
+    #[arg(long)]
+    pub log: Option<String>,
+}
+
+/// Output prefix and directory of the subcommands writing files.
+pub struct OutputPaths {
+    pub prefix: String,
+    outdir: Option<String>,
+    log: Option<String>,
+    /// Header of the files opened with `create`.
+    pub provenance: Option<Provenance>,
+}
+
+impl OutputPaths {
+    // Uses the input file name as prefix when none is given, and creates the output directory
+    pub fn new(gtf_path: &Path, args: &OutputArgs) -> anyhow::Result<Self> {
+        let OutputArgs { prefix, outdir, log } = args.clone();
+        let prefix = prefix.unwrap_or_else(|| {
+            let name = gtf_path.file_name().unwrap().to_string_lossy();
+            let name = name.strip_suffix(".gz").or_else(|| name.strip_suffix(".bgz")).unwrap_or(&name);
+            Path::new(name).file_stem().unwrap().to_string_lossy().to_string()
+        });
+        if let Some(ref outdir) = outdir {
+            std::fs::create_dir_all(outdir)?;
+        }
+        Ok(OutputPaths { prefix, outdir, log, provenance: None })
+    }
+
+    // Helper to resolve final output path respecting optional outdir
+    pub fn path(&self, name: String) -> String {
+        if let Some(ref outdir) = self.outdir {
+            Path::new(outdir).join(name).to_string_lossy().to_string()
+        } else {
+            name
+        }
+    }
+
+    /// Creates an output file and writes the provenance header.
+    pub fn create(&self, name: String) -> anyhow::Result<BufWriter<File>> {
+        let mut file = BufWriter::new(File::create(self.path(name))?);
+        if let Some(ref provenance) = self.provenance {
+            provenance.write(&mut file)?;
+        }
+        Ok(file)
+    }
+
+    /// Creates a FASTA output file, which cannot hold the provenance header.
+    pub fn create_fasta(&self, name: String) -> anyhow::Result<BufWriter<File>> {
+        Ok(BufWriter::new(File::create(self.path(name))?))
+    }
+
+    /// Creates a GFF3 output file, with the provenance header after the version directive.
+    pub fn create_gff3(&self, name: String) -> anyhow::Result<BufWriter<File>> {
+        let mut file = BufWriter::new(File::create(self.path(name))?);
+        writeln!(file, "##gff-version 3")?;
+        if let Some(ref provenance) = self.provenance {
+            provenance.write(&mut file)?;
+        }
+        Ok(file)
+    }
+
+    pub fn init_log(&self) -> anyhow::Result<()> {
+        let log_file = self.log.clone().unwrap_or_else(|| self.path(format!("{}_gamba.log", self.prefix)));
+
+        Ftail::new()
+            .datetime_format("%Y-%m-%d %H:%M:%S")
+            .console(LevelFilter::Off)
+            .single_file(Path::new(&log_file), false, LevelFilter::Info)
+            .init()?;
+        Ok(())
+    }
+}
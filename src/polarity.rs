@@ -0,0 +1,115 @@
+// polarity.rs
+use std::collections::BTreeMap;
+use crate::detect::{self, Detection, Measure};
+use crate::gtf::Transcript;
+
+// Pseudocount of the log2 expression values and ratios
+const PSEUDOCOUNT: f64 = 0.01;
+
+/// Expression gradient of an OPRN along its OpGs, 5'→3'.
+#[derive(Debug, Clone)]
+pub struct Polarity {
+    pub operon_id: String,
+    pub strand: String,
+    /// Expression of the OpGs, in transcription order.
+    pub expression: Vec<f64>,
+    /// Least-squares slope of log2(expression) against the OpG rank.
+    pub slope: f64,
+    /// log2 ratio of the first OpG to the last one.
+    pub first_last_ratio: f64,
+    /// Spearman correlation between rank and expression; `None` when the expression is flat.
+    pub spearman: Option<f64>,
+    /// Steps between consecutive OpGs where the expression drops.
+    pub decreasing_steps: usize,
+}
+
+impl Polarity {
+    /// 'flat' when every OpG has the same expression, 'decreasing' or 'increasing' when every
+    /// step goes the same way, 'mixed' otherwise.
+    pub fn trend(&self) -> &'static str {
+        let steps = self.expression.len() - 1;
+        let increasing_steps = self.expression.windows(2).filter(|pair| pair[1] > pair[0]).count();
+        if self.expression.windows(2).all(|pair| pair[1] == pair[0]) {
+            "flat"
+        } else if self.decreasing_steps == steps {
+            "decreasing"
+        } else if increasing_steps == steps {
+            "increasing"
+        } else {
+            "mixed"
+        }
+    }
+}
+
+// Ranks (1-based), averaged over ties
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&i, &j| values[i].total_cmp(&values[j]));
+    let mut ranks = vec![0.0; values.len()];
+    let mut i = 0;
+    while i < order.len() {
+        let mut j = i;
+        while j + 1 < order.len() && values[order[j + 1]] == values[order[i]] {
+            j += 1;
+        }
+        for &k in &order[i..=j] {
+            ranks[k] = (i + j) as f64 / 2.0 + 1.0;
+        }
+        i = j + 1;
+    }
+    ranks
+}
+
+fn pearson(x: &[f64], y: &[f64]) -> Option<f64> {
+    let n = x.len() as f64;
+    let (mx, my) = (x.iter().sum::<f64>() / n, y.iter().sum::<f64>() / n);
+    let sxy: f64 = x.iter().zip(y).map(|(a, b)| (a - mx) * (b - my)).sum();
+    let sxx: f64 = x.iter().map(|a| (a - mx).powi(2)).sum();
+    let syy: f64 = y.iter().map(|b| (b - my).powi(2)).sum();
+    if sxx == 0.0 || syy == 0.0 {
+        return None;
+    }
+    Some(sxy / (sxx * syy).sqrt())
+}
+
+/// Polarity of every OPRN, in the order of the operons table.
+pub fn polarity(detection: &Detection, transcripts_by_chrom: &BTreeMap<String, Vec<Transcript>>, measure: Measure) -> Vec<Polarity> {
+    detect::opgs_by_operon(detection, transcripts_by_chrom)
+        .into_iter()
+        .filter(|(_, opgs)| opgs.len() >= 2)
+        .map(|(operon_id, opgs)| {
+            let expression: Vec<f64> = opgs.iter().map(|t| measure.value(t) as f64).collect();
+            let log_expression: Vec<f64> = expression.iter().map(|e| (e + PSEUDOCOUNT).log2()).collect();
+            let position: Vec<f64> = (1..=expression.len()).map(|i| i as f64).collect();
+            let n = position.len() as f64;
+            let mean_position = position.iter().sum::<f64>() / n;
+            let mean_log = log_expression.iter().sum::<f64>() / n;
+            let slope = position.iter().zip(&log_expression).map(|(x, y)| (x - mean_position) * (y - mean_log)).sum::<f64>()
+                / position.iter().map(|x| (x - mean_position).powi(2)).sum::<f64>();
+            Polarity {
+                operon_id,
+                strand: opgs[0].strand.clone(),
+                first_last_ratio: log_expression[0] - log_expression[log_expression.len() - 1],
+                spearman: pearson(&position, &ranks(&expression)),
+                decreasing_steps: expression.windows(2).filter(|pair| pair[1] < pair[0]).count(),
+                slope,
+                expression,
+            }
+        })
+        .collect()
+}
+
+/// Two-sided exact binomial (sign) test of `k` successes out of `n` with p = 0.5.
+pub fn sign_test(k: usize, n: usize) -> f64 {
+    if n == 0 {
+        return 1.0;
+    }
+    let mut ln_factorial = vec![0.0; n + 1];
+    for i in 1..=n {
+        ln_factorial[i] = ln_factorial[i - 1] + (i as f64).ln();
+    }
+    let tail: f64 = (0..=k.min(n - k))
+        .map(|i| (ln_factorial[n] - ln_factorial[i] - ln_factorial[n - i] - n as f64 * 2f64.ln()).exp())
+        .sum();
+    (2.0 * tail).min(1.0)
+}
@@ -0,0 +1,137 @@
+// predict.rs
+use std::{collections::{BTreeMap, HashSet}, io::Write};
+use itertools::Itertools;
+use crate::detect::Detection;
+use crate::gtf::{Annotation, Transcript, transcripts_by_id};
+
+/// Annotation-only operon prediction: runs of adjacent genes on the same strand, consecutive
+/// genes no further apart than `max_intergenic` (overlapping genes are at a negative distance),
+/// with at least `min_genes` genes. Every run is an OPRN without container ('.').
+pub fn predict_operons(genes: &Annotation, max_intergenic: i64, min_genes: usize) -> Detection {
+    let mut detection = Detection::default();
+    let mut counter = 0;
+    for (chrom, chrom_genes) in &genes.transcripts_by_chrom {
+        log::info!("Processing chromosome {} ({} genes)...", chrom, chrom_genes.len());
+        // Genes are sorted by position; a gene on the other strand breaks the run
+        let mut runs: Vec<Vec<&Transcript>> = Vec::new();
+        for gene in chrom_genes {
+            match runs.last_mut() {
+                Some(run) if run.last().unwrap().strand == gene.strand
+                    && gene.start as i64 - run.last().unwrap().end as i64 - 1 <= max_intergenic => run.push(gene),
+                _ => runs.push(vec![gene]),
+            }
+        }
+        for run in runs.into_iter().filter(|run| run.len() >= min_genes.max(2)) {
+            counter += 1;
+            let operon_id = format!("OPRN.{}", counter);
+            for gene in &run {
+                detection.operons.push((operon_id.clone(), ".".to_string(), gene.id.clone()));
+                detection.gene_ids.insert(gene.id.clone());
+                detection.all_gene_gids.insert(gene.gene_id.clone());
+            }
+            detection.operon_gene_map.insert(operon_id, run.iter().map(|g| g.id.clone()).collect());
+        }
+    }
+    detection.good_cov_ids = genes.transcripts_by_chrom.values().flatten().map(|g| g.id.clone()).collect();
+    detection.order_transcriptionally(&genes.transcripts_by_chrom);
+    detection
+}
+
+/// Agreement of a predicted OPRN with the OPRNs of an expression-based run.
+#[derive(Debug, Clone)]
+pub struct CallMatch {
+    pub operon_id: String,
+    pub genes: Vec<String>,
+    /// Called OPRNs sharing at least one gene with the prediction.
+    pub called: Vec<String>,
+    /// Largest number of genes of the prediction found in a single called OPRN.
+    pub shared: usize,
+}
+
+impl CallMatch {
+    /// 'full' when one called OPRN holds every gene, 'partial' when one holds at least two,
+    /// 'none' otherwise.
+    pub fn status(&self) -> &'static str {
+        if self.shared == self.genes.len() {
+            "full"
+        } else if self.shared >= 2 {
+            "partial"
+        } else {
+            "none"
+        }
+    }
+}
+
+/// Matches the predicted OPRNs against called ones: a called OpG with the ID of a gene is that
+/// gene (prokaryotic mode), any other one stands for the genes it overlaps on the same strand in
+/// `called_transcripts`. Returns the matches and the number of called OPRNs with at least two
+/// genes in one prediction.
+pub fn compare_with_calls(
+    prediction: &Detection,
+    genes: &Annotation,
+    calls: &[(String, String, String)],
+    called_transcripts: &BTreeMap<String, Vec<Transcript>>,
+) -> (Vec<CallMatch>, usize) {
+    let gene_ids: HashSet<&str> = genes.transcripts_by_chrom.values().flatten().map(|g| g.id.as_str()).collect();
+    let called_by_id = transcripts_by_id(called_transcripts);
+    // Predicted genes of every called OPRN
+    let mut called_genes: BTreeMap<&str, HashSet<&str>> = BTreeMap::new();
+    let mut missing = 0;
+    for (operon_id, _, member) in calls {
+        let entry = called_genes.entry(operon_id.as_str()).or_default();
+        if let Some(gene_id) = gene_ids.get(member.as_str()) {
+            entry.insert(gene_id);
+            continue;
+        }
+        let Some(opg) = called_by_id.get(member.as_str()) else {
+            missing += 1;
+            continue;
+        };
+        let chrom_genes = genes.transcripts_by_chrom.get(&opg.chrom).map(|g| g.as_slice()).unwrap_or_default();
+        entry.extend(
+            chrom_genes
+                .iter()
+                .take_while(|g| g.start <= opg.end)
+                .filter(|g| g.end >= opg.start && g.strand == opg.strand)
+                .map(|g| g.id.as_str()),
+        );
+    }
+    if missing > 0 {
+        log::warn!("{} OpGs of the called operons are neither genes of the annotation nor transcripts of '--calls-gtf'", missing);
+    }
+
+    let matches: Vec<CallMatch> = prediction.operons
+        .iter()
+        .map(|(operon_id, _, _)| operon_id)
+        .unique()
+        .map(|operon_id| {
+            let members = &prediction.operon_gene_map[operon_id];
+            let shared_by_call: Vec<(&str, usize)> = called_genes
+                .iter()
+                .map(|(called_id, called)| (*called_id, members.iter().filter(|g| called.contains(g.as_str())).count()))
+                .filter(|(_, shared)| *shared > 0)
+                .collect();
+            CallMatch {
+                operon_id: operon_id.clone(),
+                genes: members.clone(),
+                called: shared_by_call.iter().map(|(called_id, _)| called_id.to_string()).collect(),
+                shared: shared_by_call.iter().map(|(_, shared)| *shared).max().unwrap_or(0),
+            }
+        })
+        .collect();
+    let supported_calls = called_genes
+        .values()
+        .filter(|called| prediction.operon_gene_map.values().any(|members| members.iter().filter(|g| called.contains(g.as_str())).count() >= 2))
+        .count();
+    (matches, supported_calls)
+}
+
+/// Writes the comparison table of `compare_with_calls`.
+pub fn write_comparison(file: &mut dyn Write, matches: &[CallMatch]) -> anyhow::Result<()> {
+    writeln!(file, "Predicted_operon\tGenes\tCalled_operons\tShared_genes\tStatus")?;
+    for m in matches {
+        let called = if m.called.is_empty() { ".".to_string() } else { m.called.join(",") };
+        writeln!(file, "{}\t{}\t{}\t{}\t{}", m.operon_id, m.genes.join(","), called, m.shared, m.status())?;
+    }
+    Ok(())
+}
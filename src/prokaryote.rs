@@ -0,0 +1,164 @@
+// prokaryote.rs
+use std::{collections::{BTreeMap, HashMap, HashSet}, path::Path};
+use crate::detect::{self, ContainerTrace, Decision, Detection, Params};
+use crate::gtf::{self, Annotation, Transcript};
+use crate::longread::exonic_overlap;
+
+/// Rules replacing the exon-based criteria in prokaryotic mode.
+#[derive(Debug, Clone)]
+pub struct ProkaryoteParams {
+    /// Fraction of a gene (CDS) that the exons of a container must cover.
+    pub min_gene_fraction: f32,
+    /// Largest distance (bp) between consecutive genes of an operon; overlapping genes are at
+    /// a negative distance.
+    pub max_intergenic: i64,
+}
+
+impl ProkaryoteParams {
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("min_gene_fraction", self.min_gene_fraction.to_string()),
+            ("max_intergenic", self.max_intergenic.to_string()),
+        ]
+    }
+}
+
+// Chromosome, strand, CDS span and span of the other records of a gene
+type GeneSpans = (String, String, Option<(u64, u64)>, (u64, u64));
+
+/// Genes of a GTF annotation, plain or BGZF-compressed, one single-exon `Transcript` per gene_id
+/// (ID and gene_id), spanning its CDS records or, without CDS, all its gene/transcript/exon
+/// records. Invalid records are an error, or are logged and skipped with `skip_invalid`.
+pub fn read_genes(path: &Path, skip_invalid: bool) -> anyhow::Result<Annotation> {
+    let mut spans: HashMap<String, GeneSpans> = HashMap::new();
+    let mut order: Vec<String> = Vec::new();
+    let mut raw_lines_by_id: HashMap<String, Vec<String>> = HashMap::new();
+    let mut without_id = 0;
+    let skipped = gtf::read_records(path, skip_invalid, |record| {
+        if !["gene", "CDS", "transcript", "exon"].iter().any(|ty| record.ty() == *ty) {
+            return Ok(());
+        }
+        let Some(gene_id) = gtf::string_attribute(&record, "gene_id")? else {
+            without_id += 1;
+            return Ok(());
+        };
+        let (start, end) = (record.start().get() as u64, record.end().get() as u64);
+        let extend = |span: (u64, u64)| (span.0.min(start), span.1.max(end));
+        let entry = spans.entry(gene_id.clone()).or_insert_with(|| {
+            order.push(gene_id.clone());
+            (record.reference_sequence_name().to_string(), format!("{:?}", record.strand()), None, (start, end))
+        });
+        if record.ty() == "CDS" {
+            entry.2 = Some(entry.2.map_or((start, end), extend));
+        } else {
+            entry.3 = extend(entry.3);
+        }
+        raw_lines_by_id.entry(gene_id).or_default().push(gtf::raw_line(&record)?);
+        Ok(())
+    })?;
+    if skipped > 0 {
+        log::warn!("Skipped {} invalid records", skipped);
+    }
+    if without_id > 0 {
+        log::warn!("{}: {} records without gene_id ignored", path.display(), without_id);
+    }
+
+    let mut transcripts_by_chrom: BTreeMap<String, Vec<Transcript>> = BTreeMap::new();
+    for gene_id in order {
+        let (chrom, strand, cds, span) = spans.remove(&gene_id).unwrap();
+        let (start, end) = cds.unwrap_or(span);
+        transcripts_by_chrom.entry(chrom.clone()).or_default().push(Transcript {
+            id: gene_id.clone(),
+            gene_id: gene_id.clone(),
+            chrom,
+            start,
+            end,
+            strand,
+            coverage: 0.0,
+            fpkm_val: 0.0,
+            score: 0.0,
+            exons: vec![(start, end)],
+            raw_lines: raw_lines_by_id.get(&gene_id).cloned().unwrap_or_default(),
+        });
+    }
+    for genes in transcripts_by_chrom.values_mut() {
+        genes.sort_by_key(|g| (g.start, g.end));
+    }
+    Ok(Annotation { transcripts_by_chrom, raw_lines_by_id })
+}
+
+/// Prokaryotic detection: every transcript is a container whose OpGs are the annotated genes
+/// its exons cover with more than `threshold` times its coverage, split where consecutive genes
+/// are further apart than `max_intergenic`; the largest run of two or more genes is claimed,
+/// unless a higher-coverage isoform contains the container. Claims are then resolved as in the
+/// default mode.
+pub fn detect_operons(transcripts_by_chrom: &BTreeMap<String, Vec<Transcript>>, genes: &Annotation, params: &Params, prokaryote: &ProkaryoteParams) -> Detection {
+    let mut operon_to_genes = Vec::new();
+    let mut good_cov_ids = HashSet::new();
+    let mut traces = Vec::new();
+    let Params { threshold, tolerance, .. } = *params;
+    for (chrom, transcripts) in transcripts_by_chrom {
+        log::info!("Processing chromosome {} ({} transcripts)...", chrom, transcripts.len());
+        let chrom_genes = genes.transcripts_by_chrom.get(chrom).map(|g| g.as_slice()).unwrap_or_default();
+        for container in transcripts {
+            good_cov_ids.insert(container.id.clone());
+            let exons = if container.exons.is_empty() { vec![(container.start, container.end)] } else { container.exons.clone() };
+            let covered: Vec<&Transcript> = chrom_genes
+                .iter()
+                .take_while(|g| g.start <= container.end)
+                .filter(|g| g.end >= container.start && g.strand == container.strand)
+                .filter(|g| exonic_overlap(&exons, &g.exons) as f32 >= prokaryote.min_gene_fraction * (g.end - g.start + 1) as f32)
+                .filter(|g| container.coverage * threshold < g.coverage)
+                .collect();
+            // Transcripts containing the container with more than `threshold` times its coverage, as in
+            // the default mode without the monoexonic multiplier
+            let isoforms: Vec<String> = transcripts
+                .iter()
+                .filter(|t| t.id != container.id && t.strand == container.strand)
+                .filter(|t| detect::transcripts_inside(t, container, tolerance, threshold, threshold))
+                .map(|t| t.id.clone())
+                .collect();
+
+            // Runs of genes no further apart than max_intergenic; the largest one is kept
+            let mut runs: Vec<Vec<&Transcript>> = Vec::new();
+            for gene in &covered {
+                match runs.last_mut() {
+                    Some(run) if gene.start as i64 - run.last().unwrap().end as i64 - 1 <= prokaryote.max_intergenic => run.push(gene),
+                    _ => runs.push(vec![gene]),
+                }
+            }
+            let best = runs.into_iter().rev().max_by_key(|run| run.len()).unwrap_or_default();
+
+            let mut trace = ContainerTrace {
+                container_id: container.id.clone(),
+                chrom: container.chrom.clone(),
+                strand: container.strand.clone(),
+                start: container.start,
+                end: container.end,
+                coverage: container.coverage,
+                container_exons: container.exons.len(),
+                contained: covered.iter().map(|g| g.id.clone()).collect(),
+                isoforms: isoforms.clone(),
+                selected: best.iter().map(|g| g.id.clone()).collect(),
+                opg_exons: 0,
+                operon: None,
+                decision: match covered.len() {
+                    0 => Decision::NoContained,
+                    1 => Decision::FewContained,
+                    _ if !isoforms.is_empty() => Decision::HigherCovIsoform,
+                    _ => Decision::IntergenicGap,
+                },
+            };
+            if best.len() >= 2 && isoforms.is_empty() {
+                trace.decision = Decision::Claimed;
+                for gene in best {
+                    operon_to_genes.push((container.gene_id.clone(), container.clone(), gene));
+                }
+            }
+            traces.push(trace);
+        }
+    }
+    let mut detection = detect::resolve_claims(operon_to_genes, good_cov_ids, traces, params);
+    detection.order_transcriptionally(&genes.transcripts_by_chrom);
+    detection
+}
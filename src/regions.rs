@@ -0,0 +1,170 @@
+// regions.rs
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs::File, io::{BufRead, BufReader}, path::Path};
+use crate::gtf::{self, Annotation, Transcript};
+
+/// Genomic interval, 1-based and inclusive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Parses 'chr:start-end' (1-based, inclusive).
+pub fn parse_region(s: &str) -> Result<Region, String> {
+    let (chrom, range) = s.rsplit_once(':').ok_or_else(|| format!("'{}' is not of the form chr:start-end", s))?;
+    let (start, end) = range.split_once('-').ok_or_else(|| format!("'{}' is not of the form chr:start-end", s))?;
+    let start: u64 = start.replace(',', "").parse().map_err(|_| format!("invalid start in '{}'", s))?;
+    let end: u64 = end.replace(',', "").parse().map_err(|_| format!("invalid end in '{}'", s))?;
+    if chrom.is_empty() || start == 0 || end < start {
+        return Err(format!("invalid region '{}'", s));
+    }
+    Ok(Region { chrom: chrom.to_string(), start, end })
+}
+
+/// Reads the regions of a BED file (0-based, half-open).
+pub fn read_bed(path: &Path) -> anyhow::Result<Vec<Region>> {
+    let mut regions = Vec::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 3 {
+            anyhow::bail!("{}:{}: expected at least three columns", path.display(), i + 1);
+        }
+        let start: u64 = fields[1].parse().map_err(|_| anyhow::anyhow!("{}:{}: invalid start '{}'", path.display(), i + 1, fields[1]))?;
+        let end: u64 = fields[2].parse().map_err(|_| anyhow::anyhow!("{}:{}: invalid end '{}'", path.display(), i + 1, fields[2]))?;
+        regions.push(Region { chrom: fields[0].to_string(), start: start + 1, end });
+    }
+    Ok(regions)
+}
+
+/// Regions and whole chromosomes the analysis is restricted to.
+#[derive(Debug, Clone, Default)]
+pub struct RegionFilter {
+    pub regions: Vec<Region>,
+    pub chroms: HashSet<String>,
+}
+
+impl RegionFilter {
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty() && self.chroms.is_empty()
+    }
+
+    // Clusters of overlapping transcripts (any strand) of one chromosome
+    fn loci(transcripts: &[Transcript]) -> Vec<(u64, u64, Vec<usize>)> {
+        let mut order: Vec<usize> = (0..transcripts.len()).collect();
+        order.sort_by_key(|&i| transcripts[i].start);
+        let mut loci: Vec<(u64, u64, Vec<usize>)> = Vec::new();
+        for i in order {
+            let t = &transcripts[i];
+            match loci.last_mut() {
+                Some((_, end, members)) if t.start <= *end => {
+                    *end = (*end).max(t.end);
+                    members.push(i);
+                }
+                _ => loci.push((t.start, t.end, vec![i])),
+            }
+        }
+        loci
+    }
+
+    // Sorted, merged intervals of each chromosome
+    fn merge(intervals: &mut Vec<(u64, u64)>) {
+        intervals.sort();
+        let mut merged: Vec<(u64, u64)> = Vec::new();
+        for &(start, end) in intervals.iter() {
+            match merged.last_mut() {
+                Some((_, last_end)) if start <= *last_end + 1 => *last_end = (*last_end).max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        *intervals = merged;
+    }
+
+    /// Reads from an indexed BGZF GTF only the records needed by the filter. The query
+    /// intervals are widened to the loci they touch until no new transcript is fetched, so that
+    /// `apply` keeps the same transcripts as on a full read.
+    pub fn read_indexed(&self, gtf_path: &Path, index_path: &Path, skip_invalid: bool) -> anyhow::Result<Annotation> {
+        let mut intervals: BTreeMap<String, Vec<(u64, u64)>> = BTreeMap::new();
+        for region in self.regions.iter().filter(|r| !self.chroms.contains(&r.chrom)) {
+            intervals.entry(region.chrom.clone()).or_default().push((region.start, region.end));
+        }
+        intervals.values_mut().for_each(Self::merge);
+
+        let mut rounds = 0;
+        loop {
+            rounds += 1;
+            let mut queries: Vec<(String, Option<(u64, u64)>)> = self.chroms.iter().map(|c| (c.clone(), None)).collect();
+            queries.sort();
+            for (chrom, chrom_intervals) in &intervals {
+                queries.extend(chrom_intervals.iter().map(|&i| (chrom.clone(), Some(i))));
+            }
+            let annotation = gtf::read_annotation_indexed(gtf_path, index_path, &queries, skip_invalid)?;
+
+            let mut widened = intervals.clone();
+            for (chrom, chrom_intervals) in widened.iter_mut() {
+                let Some(transcripts) = annotation.transcripts_by_chrom.get(chrom) else { continue };
+                let touched: Vec<(u64, u64)> = Self::loci(transcripts)
+                    .into_iter()
+                    .filter(|(start, end, _)| chrom_intervals.iter().any(|&(s, e)| s <= *end && *start <= e))
+                    .map(|(start, end, _)| (start, end))
+                    .collect();
+                chrom_intervals.extend(touched);
+                Self::merge(chrom_intervals);
+            }
+            if widened == intervals {
+                log::info!(
+                    "Read {} transcripts from {} through its index ({} queries, {} rounds)",
+                    annotation.transcripts_by_chrom.values().map(|t| t.len()).sum::<usize>(),
+                    gtf_path.display(),
+                    queries.len(),
+                    rounds
+                );
+                return Ok(annotation);
+            }
+            intervals = widened;
+        }
+    }
+
+    /// Keeps the transcripts of the selected chromosomes, and those of every locus (cluster of
+    /// overlapping transcripts) touching a region, so that a container partially overlapping a
+    /// region is kept together with all the transcripts it spans.
+    pub fn apply(&self, annotation: &mut Annotation) {
+        let total: usize = annotation.transcripts_by_chrom.values().map(|t| t.len()).sum();
+        let mut regions_by_chrom: HashMap<&str, Vec<&Region>> = HashMap::new();
+        for region in &self.regions {
+            regions_by_chrom.entry(region.chrom.as_str()).or_default().push(region);
+        }
+
+        for (chrom, transcripts) in annotation.transcripts_by_chrom.iter_mut() {
+            if self.chroms.contains(chrom) {
+                continue;
+            }
+            let regions = regions_by_chrom.get(chrom.as_str()).map(|r| r.as_slice()).unwrap_or(&[]);
+            let mut keep = vec![false; transcripts.len()];
+            for (start, end, members) in Self::loci(transcripts) {
+                if regions.iter().any(|r| r.start <= end && start <= r.end) {
+                    for i in members {
+                        keep[i] = true;
+                    }
+                }
+            }
+            let mut keep = keep.into_iter();
+            transcripts.retain(|_| keep.next().unwrap());
+        }
+        annotation.transcripts_by_chrom.retain(|_, transcripts| !transcripts.is_empty());
+
+        let kept_ids: HashSet<&str> = annotation.transcripts_by_chrom.values().flatten().map(|t| t.id.as_str()).collect();
+        annotation.raw_lines_by_id.retain(|id, _| kept_ids.contains(id.as_str()));
+        log::info!(
+            "Restricted to {} regions and {} chromosomes: kept {} of {} transcripts",
+            self.regions.len(),
+            self.chroms.len(),
+            kept_ids.len(),
+            total
+        );
+    }
+}
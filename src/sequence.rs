@@ -0,0 +1,78 @@
+// sequence.rs
+use std::{fs::File, io::Write, path::{Path, PathBuf}};
+use noodles::{core::{Position, Region}, fasta};
+use crate::gtf::Transcript;
+
+/// Genome FASTA with random access through its '.fai' index.
+pub struct Genome {
+    reader: fasta::io::IndexedReader<fasta::io::BufReader<File>>,
+}
+
+impl Genome {
+    /// Opens the genome; without a '.fai' next to it, the index is built in memory.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let index_path = PathBuf::from(format!("{}.fai", path.display()));
+        let builder = if index_path.exists() {
+            fasta::io::indexed_reader::Builder::default()
+        } else {
+            log::warn!("{} not found, indexing {} (run 'samtools faidx' to avoid it)", index_path.display(), path.display());
+            fasta::io::indexed_reader::Builder::default().set_index(fasta::fs::index(path)?)
+        };
+        let reader = builder.build_from_path(path).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+        Ok(Genome { reader })
+    }
+
+    /// Sequence of chrom:start-end (1-based, inclusive), on the forward strand.
+    pub fn fetch(&mut self, chrom: &str, start: u64, end: u64) -> anyhow::Result<Vec<u8>> {
+        let region = Region::new(chrom, Position::try_from(start as usize)?..=Position::try_from(end as usize)?);
+        let record = self.reader.query(&region).map_err(|e| anyhow::anyhow!("{}:{}-{}: {}", chrom, start, end, e))?;
+        Ok(record.sequence().as_ref().to_vec())
+    }
+
+    /// Spliced sequence of a transcript, 5'→3' on its strand; a transcript without exons is
+    /// taken as a single exon.
+    pub fn spliced(&mut self, transcript: &Transcript) -> anyhow::Result<Vec<u8>> {
+        let mut exons = transcript.exons.clone();
+        if exons.is_empty() {
+            exons.push((transcript.start, transcript.end));
+        }
+        exons.sort();
+        let mut seq = Vec::new();
+        for (start, end) in exons {
+            seq.extend(self.fetch(&transcript.chrom, start, end)?);
+        }
+        if transcript.strand == "Reverse" {
+            seq = reverse_complement(&seq);
+        }
+        Ok(seq)
+    }
+}
+
+/// Reverse complement, keeping the case and any IUPAC code other than ACGTU as N.
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|b| match b {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' | b'U' => b'A',
+            b'a' => b't',
+            b'c' => b'g',
+            b'g' => b'c',
+            b't' | b'u' => b'a',
+            b'n' => b'n',
+            _ => b'N',
+        })
+        .collect()
+}
+
+/// Writes a FASTA record, wrapped at 60 bases.
+pub fn write_fasta(out: &mut dyn Write, header: &str, seq: &[u8]) -> anyhow::Result<()> {
+    writeln!(out, ">{}", header)?;
+    for line in seq.chunks(60) {
+        out.write_all(line)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+// stats.rs
+use std::{collections::HashSet, path::PathBuf};
+use crate::gtf;
+
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+    /// Path to the input GTF file.
+    #[arg(short, long)]
+    file: PathBuf,
+
+    /// Log and skip malformed GTF records instead of stopping at the first one.
+    #[arg(long)]
+    skip_invalid: bool,
+}
+
+// Median of a non-empty, unsorted list
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) { (values[mid - 1] + values[mid]) / 2.0 } else { values[mid] }
+}
+
+/// Prints a two-column table of counts and expression statistics of the input GTF.
+pub fn run(args: StatsArgs) -> anyhow::Result<()> {
+    let annotation = gtf::read_annotation(&args.file, args.skip_invalid)?;
+    let transcripts: Vec<&gtf::Transcript> = annotation.transcripts_by_chrom.values().flatten().collect();
+
+    let genes: HashSet<&str> = transcripts.iter().map(|t| t.gene_id.as_str()).collect();
+    let monoexonic = transcripts.iter().filter(|t| t.exons.len() == 1).count();
+    let without_exons = transcripts.iter().filter(|t| t.exons.is_empty()).count();
+    let exons: usize = transcripts.iter().map(|t| t.exons.len()).sum();
+    let forward = transcripts.iter().filter(|t| gtf::strand_symbol(&t.strand) == "+").count();
+    let reverse = transcripts.iter().filter(|t| gtf::strand_symbol(&t.strand) == "-").count();
+    let zero_cov = transcripts.iter().filter(|t| t.coverage == 0.0).count();
+    let zero_fpkm = transcripts.iter().filter(|t| t.fpkm_val == 0.0).count();
+
+    println!("Statistic\tValue");
+    println!("Chromosomes\t{}", annotation.transcripts_by_chrom.len());
+    println!("Genes\t{}", genes.len());
+    println!("Transcripts\t{}", transcripts.len());
+    println!("Transcripts_forward\t{}", forward);
+    println!("Transcripts_reverse\t{}", reverse);
+    println!("Multiexonic_transcripts\t{}", transcripts.len() - monoexonic - without_exons);
+    println!("Monoexonic_transcripts\t{}", monoexonic);
+    println!("Transcripts_without_exons\t{}", without_exons);
+    println!("Exons\t{}", exons);
+    println!("Transcripts_zero_cov\t{}", zero_cov);
+    println!("Transcripts_zero_FPKM\t{}", zero_fpkm);
+
+    if !transcripts.is_empty() {
+        for (name, mut values) in [
+            ("Coverage", transcripts.iter().map(|t| t.coverage).collect::<Vec<f32>>()),
+            ("FPKM", transcripts.iter().map(|t| t.fpkm_val).collect::<Vec<f32>>()),
+        ] {
+            let mean = values.iter().sum::<f32>() / values.len() as f32;
+            let median = median(&mut values);
+            println!("{}_min\t{}", name, values[0]);
+            println!("{}_median\t{}", name, median);
+            println!("{}_mean\t{:.3}", name, mean);
+            println!("{}_max\t{}", name, values[values.len() - 1]);
+        }
+    }
+    Ok(())
+}
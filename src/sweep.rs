@@ -0,0 +1,90 @@
+// sweep.rs
+use std::io::Write;
+use itertools::iproduct;
+use crate::detect::{self, Params};
+use crate::gtf::Annotation;
+use crate::output::OutputPaths;
+
+/// Values evaluated for each parameter; an empty list keeps the base value.
+#[derive(Debug, Clone, Default)]
+pub struct SweepGrid {
+    pub thresholds: Vec<f32>,
+    pub monoexonic_ts: Vec<f32>,
+    pub min_overlaps: Vec<f32>,
+    pub bp_overlaps: Vec<u64>,
+}
+
+impl SweepGrid {
+    pub fn is_empty(&self) -> bool {
+        self.thresholds.is_empty() && self.monoexonic_ts.is_empty() && self.min_overlaps.is_empty() && self.bp_overlaps.is_empty()
+    }
+
+    /// Every parameter combination of the grid, built on top of `base`.
+    pub fn combinations(&self, base: &Params) -> Vec<Params> {
+        let or_base = |values: &Vec<f32>, base: f32| if values.is_empty() { vec![base] } else { values.clone() };
+        let thresholds = or_base(&self.thresholds, base.threshold);
+        let monoexonic_ts = or_base(&self.monoexonic_ts, base.monoexonic_t);
+        let min_overlaps = or_base(&self.min_overlaps, base.min_overlap);
+        let bp_overlaps = if self.bp_overlaps.is_empty() { vec![base.bp_overlap] } else { self.bp_overlaps.clone() };
+
+        iproduct!(thresholds, monoexonic_ts, min_overlaps, bp_overlaps)
+            .map(|(threshold, monoexonic_t, min_overlap, bp_overlap)| Params {
+                threshold,
+                monoexonic_t,
+                min_overlap,
+                bp_overlap,
+                ..base.clone()
+            })
+            .collect()
+    }
+}
+
+/// Runs the detection for every combination of the grid on an already parsed annotation.
+/// Writes one row of counts per combination and, with `write_sets`, the operons found by each one.
+pub fn run_sweep(annotation: &Annotation, base: &Params, grid: &SweepGrid, out: &OutputPaths, write_sets: bool) -> anyhow::Result<()> {
+    let combinations = grid.combinations(base);
+    log::info!("Sweep mode: evaluating {} parameter combinations", combinations.len());
+
+    let summary_path = format!("{}_sweep.tsv", out.prefix);
+    let sets_path = format!("{}_sweep_operons.tsv", out.prefix);
+    let mut summary_file = out.create(summary_path.clone())?;
+    writeln!(summary_file, "Threshold\tMonoexonic_t\tMin_overlap\tBp_overlap\tOPRNs\tOpGs\t2_genes\t3_genes\t4_genes\t5_genes\t>5_genes")?;
+    let mut sets_file = if write_sets {
+        let mut file = out.create(sets_path.clone())?;
+        writeln!(file, "Threshold\tMonoexonic_t\tMin_overlap\tBp_overlap\tOperon\tOperonTrans\tContained_transcript\tOpG_rank\tOpG_position")?;
+        Some(file)
+    } else {
+        None
+    };
+
+    for params in &combinations {
+        let detection = detect::detect_operons(&annotation.transcripts_by_chrom, params);
+        let sizes = detect::size_summary(&detection.operon_gene_map);
+        let key = format!("{}\t{}\t{}\t{}", params.threshold, params.monoexonic_t, params.min_overlap, params.bp_overlap);
+        writeln!(
+            summary_file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            key,
+            detection.operon_gene_map.len(),
+            detection.operons.len(),
+            sizes["2 genes"],
+            sizes["3 genes"],
+            sizes["4 genes"],
+            sizes["5 genes"],
+            sizes[">5 genes"],
+        )?;
+        if let Some(file) = sets_file.as_mut() {
+            for (operon_id, operon, inner_trans) in &detection.operons {
+                let (rank, size) = detection.opg_rank(operon_id, inner_trans).unwrap_or((0, 0));
+                writeln!(file, "{}\t{}\t{}\t{}\t{}\t{}", key, operon_id, operon, inner_trans, rank, detect::position_label(rank, size))?;
+            }
+        }
+        log::info!("{}: {} OPRNs, {} OpGs", key.replace('\t', " "), detection.operon_gene_map.len(), detection.operons.len());
+    }
+
+    log::info!("Sweep table written to {}", summary_path);
+    if write_sets {
+        log::info!("Sweep operon sets written to {}", sets_path);
+    }
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use std::process::Command;
 use std::str;
+use std::path::Path;
 
 #[test]
 fn test_operon_summary_output() {
@@ -37,3 +38,743 @@ fn test_operon_summary_output() {
     assert!(contains_line("5 genes: 0"));
     assert!(contains_line(">5 genes: 0"));
 }
+
+// Runs gamba on the toy fixture and returns the output directory
+fn run_toy(outdir: &str, extra_args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["-f", "tests/resources/toy_operons.gtf", "-o", outdir])
+        .args(extra_args)
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+
+    assert!(
+        output.status.success(),
+        "Program exited with error: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    outdir.to_string()
+}
+
+fn read_output(outdir: &str, name: &str) -> String {
+    std::fs::read_to_string(format!("{}/{}", outdir, name))
+        .unwrap_or_else(|_| panic!("Missing output file {}", name))
+}
+
+// Table rows, without the provenance header and the column names
+fn data_rows(content: &str) -> Vec<&str> {
+    content.lines().filter(|l| !l.starts_with('#')).skip(1).collect()
+}
+
+#[test]
+fn test_conflict_policy_longest() {
+    let outdir = run_toy(".tests/test_conflict_policy_longest", &["--conflict-policy", "longest"]);
+
+    // MSTRG.3.1 is claimed by both MSTRG.1.1 and the longer MSTRG.4.1
+    let conflicts = read_output(&outdir, "toy_operons_conflicts_t1.0.tsv");
+    let rows: Vec<Vec<&str>> = data_rows(&conflicts).iter().map(|l| l.split('\t').collect()).collect();
+    assert_eq!(rows.len(), 2);
+    assert!(rows.iter().any(|r| r[0] == "MSTRG.3.1" && r[1] == "MSTRG.1.1" && r[5] == "dropped"));
+    assert!(rows.iter().any(|r| r[0] == "MSTRG.3.1" && r[1] == "MSTRG.4.1" && r[5] == "kept"));
+
+    let operons = read_output(&outdir, "toy_operons_operons_found_t1.0.tsv");
+    assert!(operons.lines().any(|l| l.contains("\tMSTRG.4.1\tMSTRG.3.1\t")));
+    assert!(!operons.lines().any(|l| l.contains("\tMSTRG.1.1\tMSTRG.3.1\t")));
+}
+
+#[test]
+fn test_optimal_selection() {
+    // Greedy keeps only MSTRG.13.1 (highest FPKM), which overlaps both of its neighbours
+    let greedy = run_toy(".tests/test_optimal_selection_greedy", &[]);
+    let operons = read_output(&greedy, "toy_operons_operons_found_t1.0.tsv");
+    assert!(!operons.contains("MSTRG.11.1"));
+
+    let optimal = run_toy(".tests/test_optimal_selection", &["--selection", "optimal", "--objective", "fpkm"]);
+    let operons = read_output(&optimal, "toy_operons_operons_found_t1.0.tsv");
+    let opgs: Vec<&str> = operons
+        .lines()
+        .filter(|l| l.contains("\tMSTRG.11.1\t"))
+        .map(|l| l.split('\t').nth(2).unwrap())
+        .collect();
+    assert_eq!(opgs, ["MSTRG.12.1", "MSTRG.14.1"]);
+}
+
+#[test]
+fn test_parameter_sweep() {
+    let outdir = run_toy(".tests/test_parameter_sweep", &["--sweep-threshold", "1,5", "--sweep-monoexonic-t", "10,20", "--sweep-sets"]);
+
+    let sweep = read_output(&outdir, "toy_operons_sweep.tsv");
+    let rows: Vec<Vec<&str>> = data_rows(&sweep).iter().map(|l| l.split('\t').collect()).collect();
+    assert_eq!(rows.len(), 4);
+    // Raising the threshold to 5 loses the operon whose container has coverage 3-5
+    assert!(rows.iter().any(|r| r[..4] == ["1", "10", "0.5", "50"] && r[4] == "2" && r[5] == "6"));
+    assert!(rows.iter().any(|r| r[..4] == ["5", "20", "0.5", "50"] && r[4] == "1" && r[5] == "3"));
+
+    let sets = read_output(&outdir, "toy_operons_sweep_operons.tsv");
+    assert_eq!(data_rows(&sets).len(), 6 + 6 + 3 + 3);
+    assert!(!Path::new(&outdir).join("toy_operons_operons_found_t1.0.tsv").exists());
+}
+
+#[test]
+fn test_explain_trace() {
+    let outdir = run_toy(".tests/test_explain_trace", &["--explain"]);
+
+    let trace = read_output(&outdir, "toy_operons_explain_t1.0.tsv");
+    let decision = |id: &str| -> String {
+        trace
+            .lines()
+            .find(|l| l.starts_with(&format!("{}\t", id)))
+            .map(|l| l.rsplit('\t').next().unwrap().to_string())
+            .unwrap_or_else(|| panic!("No trace for {}", id))
+    };
+    assert_eq!(decision("MSTRG.1.1"), "operon");
+    assert_eq!(decision("MSTRG.5.1"), "operon");
+    assert_eq!(decision("MSTRG.9.2"), "few_contained");
+    assert_eq!(decision("MSTRG.11.1"), "overlap_pruning");
+
+    let outdir = run_toy(".tests/test_explain_trace_single", &["--explain-transcript", "MSTRG.9.1"]);
+    let trace = read_output(&outdir, "toy_operons_explain_t1.0.tsv");
+    let rows = data_rows(&trace);
+    assert_eq!(rows.len(), 1);
+    assert!(rows[0].ends_with("\tno_contained"));
+}
+
+#[test]
+fn test_subcommands() {
+    // Explicit 'detect' behaves as the flat command line
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["detect", "-f", "tests/resources/toy_operons.gtf", "-o", ".tests/test_subcommands"])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "detect failed: {}", String::from_utf8_lossy(&output.stderr));
+    let operons_path = ".tests/test_subcommands/toy_operons_operons_found_t1.0.tsv";
+    let operons = std::fs::read_to_string(operons_path).expect("Missing operons table");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["stats", "-f", "tests/resources/toy_operons.gtf"])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.lines().any(|l| l == "Transcripts\t15"));
+    assert!(stdout.lines().any(|l| l == "Chromosomes\t3"));
+
+    // A run evaluated against itself is perfect
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["eval", "--predicted", operons_path, "--truth", operons_path])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.lines().any(|l| l.starts_with("Operon\t2\t2\t2\t1.0000\t1.0000\t1.0000")));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["export", "-f", "tests/resources/toy_operons.gtf", "--operons", operons_path, "-o", ".tests/test_subcommands_export"])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "export failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(data_rows(&read_output(".tests/test_subcommands_export", "toy_operons_operons_found_t1.0.tsv")), data_rows(&operons));
+    assert_eq!(
+        data_rows(&read_output(".tests/test_subcommands_export", "toy_operons_Operons_t1.0.gtf")),
+        data_rows(&read_output(".tests/test_subcommands", "toy_operons_Operons_t1.0.gtf"))
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["convert", "-f", ".tests/test_subcommands/toy_operons_Operons_t1.0.gtf", "--to", "bed"])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.lines().any(|l| l == "chr1\t999\t5000\tMSTRG.1.1\t0\t+\t999\t5000\t0\t2\t501,501,\t0,3500,"));
+}
+
+#[test]
+fn test_config_and_preset() {
+    let outdir = run_toy(".tests/test_config_and_preset", &["--config", "tests/resources/toy_params.toml", "--preset", "nematode"]);
+
+    // The threshold comes from the config file, and names the outputs
+    let operons = read_output(&outdir, "toy_operons_operons_found_t5.0.tsv");
+    assert!(operons.contains("MSTRG.1.1"));
+    assert!(!operons.contains("MSTRG.5.1"));
+
+    let log = read_output(&outdir, "toy_operons_gamba.log");
+    assert!(log.contains("Parameter threshold = 5.0 (config)"));
+    assert!(log.contains("Parameter tolerance = 150 (preset)"));
+    assert!(log.contains("Parameter objective = Fpkm (default)"));
+
+    // Command-line values take precedence over the config file
+    let outdir = run_toy(".tests/test_config_and_preset_cli", &["--config", "tests/resources/toy_params.toml", "-t", "1"]);
+    let log = read_output(&outdir, "toy_operons_gamba.log");
+    assert!(log.contains("Parameter threshold = 1.0 (command line)"));
+    assert!(log.contains("Parameter selection = Optimal (config)"));
+}
+
+#[test]
+fn test_provenance_header() {
+    let outdir = run_toy(".tests/test_provenance_header", &["-t", "2"]);
+
+    for name in ["toy_operons_operons_found_t2.0.tsv", "toy_operons_Operons_t2.0.gtf", "toy_operons_opCLEAN_t2.0.gtf"] {
+        let content = read_output(&outdir, name);
+        let header: Vec<&str> = content.lines().take_while(|l| l.starts_with('#')).collect();
+        assert_eq!(header[0], format!("#gamba-version {}", env!("CARGO_PKG_VERSION")));
+        assert!(header.iter().any(|l| l.starts_with("#gamba-command ") && l.contains("-t 2")));
+        assert!(header.iter().any(|l| l.starts_with("#gamba-input ") && l.ends_with("toy_operons.gtf")));
+        assert!(header.iter().any(|l| l.starts_with("#gamba-input-sha256 ") && l.len() == "#gamba-input-sha256 ".len() + 64));
+        assert!(header.iter().any(|l| l.starts_with("#gamba-date ")));
+        assert!(header.contains(&"#gamba-param threshold=2"));
+        assert!(header.contains(&"#gamba-param tolerance=250"));
+    }
+}
+
+#[test]
+fn test_region_restriction() {
+    // The region only touches the end of MSTRG.1.1: the whole locus is kept
+    let outdir = run_toy(".tests/test_region_restriction", &["--region", "chr1:4800-4900"]);
+    let rows = data_rows(&read_output(&outdir, "toy_operons_operons_found_t1.0.tsv")).join("\n");
+    assert!(rows.contains("MSTRG.1.1\tMSTRG.2.1"));
+    assert!(!rows.contains("MSTRG.5.1"));
+    let clean = read_output(&outdir, "toy_operons_opCLEAN_t1.0.gtf");
+    assert!(!clean.contains("chr2\t"));
+
+    let outdir = run_toy(".tests/test_region_restriction_chroms", &["--chroms", "chr2,chr3"]);
+    assert!(data_rows(&read_output(&outdir, "toy_operons_operons_found_t1.0.tsv")).is_empty());
+    let clean = read_output(&outdir, "toy_operons_opCLEAN_t1.0.gtf");
+    assert!(clean.contains("MSTRG.9.1"));
+    assert!(!clean.contains("chr1\t"));
+}
+
+#[test]
+fn test_indexed_gtf() {
+    // Same region as above, read through the tabix index of the BGZF-compressed fixture
+    let outdir = ".tests/test_indexed_gtf";
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["-f", "tests/resources/toy_operons.gtf.gz", "-o", outdir, "--region", "chr1:4800-4900"])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+
+    let rows = data_rows(&read_output(outdir, "toy_operons_operons_found_t1.0.tsv")).join("\n");
+    assert!(rows.contains("MSTRG.1.1\tMSTRG.2.1"));
+    assert!(!rows.contains("MSTRG.5.1"));
+    let log = read_output(outdir, "toy_operons_gamba.log");
+    assert!(log.contains("through its index"));
+}
+
+#[test]
+fn test_invalid_records() {
+    let outdir = ".tests/test_invalid_records";
+    std::fs::create_dir_all(outdir).unwrap();
+    let gtf = std::fs::read_to_string("tests/resources/toy_operons.gtf").unwrap();
+    let gtf = gtf.replacen("transcript_id \"MSTRG.9.2\";", "transcript_id \"MSTRG.9.2\"; transcript_id \"MSTRG.9.3\";", 1);
+    let invalid_path = format!("{}/invalid.gtf", outdir);
+    std::fs::write(&invalid_path, gtf).unwrap();
+
+    // Without --skip-invalid the run stops, reporting the line of the bad record
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["-f", &invalid_path, "-o", outdir])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("invalid.gtf:33: attribute transcript_id has several values"), "{}", stderr);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["-f", &invalid_path, "-o", outdir, "--skip-invalid"])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+    let rows = data_rows(&read_output(outdir, "invalid_operons_found_t1.0.tsv")).join("\n");
+    assert!(rows.contains("MSTRG.1.1\tMSTRG.2.1"));
+    assert!(read_output(outdir, "invalid_gamba.log").contains("Skipped 1 invalid records"));
+
+    // The other subcommands reading a GTF take the same option
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["stats", "-f", &invalid_path])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("use --skip-invalid"));
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["stats", "-f", &invalid_path, "--skip-invalid"])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn test_operon_genes_table() {
+    let outdir = run_toy(".tests/test_operon_genes_table", &[]);
+    let content = read_output(&outdir, "toy_operons_operon_genes_t1.0.tsv");
+    let rows = data_rows(&content);
+    assert_eq!(rows.len(), 6);
+    assert_eq!(rows[0], "OPRN.1\t1\tfirst\tMSTRG.2\tchr1\t1000\t1500\t+\tMSTRG.2.1\t50.0000\t20.0000");
+    assert!(rows[2].starts_with("OPRN.1\t3\tlast\tMSTRG.10\t"));
+    // Minus strand: ranked from the rightmost gene
+    assert!(rows[3].starts_with("OPRN.2\t1\tfirst\tMSTRG.8\t"));
+    assert!(rows[4].starts_with("OPRN.2\t2\tinternal\tMSTRG.7\t"));
+
+    // Containers and OpGs sharing a gene_id: one row per OpG, containers left out
+    let outdir = ".tests/test_operon_genes_table_shared";
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["-f", "tests/resources/toy_shared_gene.gtf", "-o", outdir])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+    let content = read_output(outdir, "toy_shared_gene_operon_genes_t1.0.tsv");
+    assert_eq!(data_rows(&content), vec![
+        "OPRN.1\t1\tfirst\tMSTRG.1\tchr1\t1000\t1500\t+\tMSTRG.2.1\t50.0000\t20.0000",
+        "OPRN.1\t2\tinternal\tMSTRG.1\tchr1\t4000\t5000\t+\tMSTRG.3.1\t40.0000\t15.0000",
+        "OPRN.1\t3\tlast\tMSTRG.1\tchr1\t7400\t8000\t+\tMSTRG.10.1\t25.0000\t9.0000",
+    ]);
+    let features = read_output(outdir, "toy_shared_gene_operon_features_t1.0.gtf");
+    assert!(features.contains("genes \"MSTRG.1,MSTRG.1,MSTRG.1\"; containers \"MSTRG.1.1,MSTRG.4.1\"; size \"3\";"));
+}
+
+#[test]
+fn test_resolved_annotation() {
+    let outdir = run_toy(".tests/test_resolved_annotation", &[]);
+    let resolved = read_output(&outdir, "toy_operons_resolved_t1.0.gtf");
+    // Containers are replaced by their genes, which are tagged with their OPRN
+    assert!(!resolved.contains("transcript_id \"MSTRG.1.1\""));
+    assert!(!resolved.contains("transcript_id \"MSTRG.5.1\""));
+    let tagged = resolved.lines().filter(|l| l.contains("transcript_id \"MSTRG.7.1\"")).collect::<Vec<_>>();
+    assert_eq!(tagged.len(), 3);
+    assert!(tagged.iter().all(|l| l.ends_with("operon_id \"OPRN.2\";opg_rank \"2\";opg_position \"internal\";")));
+    // Non-operon transcripts are kept untagged
+    assert!(resolved.lines().any(|l| l.contains("transcript_id \"MSTRG.9.1\"") && !l.contains("operon_id")));
+}
+
+#[test]
+fn test_operon_features() {
+    let outdir = run_toy(".tests/test_operon_features", &["--operon-span", "containers"]);
+    let gtf = read_output(&outdir, "toy_operons_operon_features_t1.0.gtf");
+    let rows: Vec<&str> = gtf.lines().filter(|l| !l.starts_with('#')).collect();
+    assert_eq!(rows.len(), 2);
+    assert!(rows[0].starts_with("chr1\tgamba\toperon\t1000\t8000\t38.3333\t+\t.\tgene_id \"OPRN.1\";"));
+    assert!(rows[0].contains("genes \"MSTRG.2,MSTRG.3,MSTRG.10\"; containers \"MSTRG.1.1,MSTRG.4.1\"; size \"3\";"));
+
+    let gff3 = read_output(&outdir, "toy_operons_operon_features_t1.0.gff3");
+    assert!(gff3.starts_with("##gff-version 3\n#gamba-version"));
+    assert!(gff3.contains("\tID=OPRN.2;genes=MSTRG.8,MSTRG.7,MSTRG.6;containers=MSTRG.5.1;size=3\n"));
+}
+
+#[test]
+fn test_intercistronic_regions() {
+    let outdir = run_toy(".tests/test_intercistronic_regions", &["--genome", "tests/resources/toy_genome.fa"]);
+    let bed = read_output(&outdir, "toy_operons_intercistronic_t1.0.bed");
+    let rows: Vec<&str> = bed.lines().filter(|l| !l.starts_with('#')).collect();
+    assert_eq!(rows.len(), 4);
+    assert_eq!(rows[0], "chr1\t1500\t3999\tOPRN.1_ICR1\t0\t+\tOPRN.1\tMSTRG.2\tMSTRG.3");
+    // Minus strand: numbered and oriented from the rightmost OpG
+    assert_eq!(rows[2], "chr1\t12300\t13899\tOPRN.2_ICR1\t0\t-\tOPRN.2\tMSTRG.8\tMSTRG.7");
+
+    let fasta = read_output(&outdir, "toy_operons_intercistronic_t1.0.fa");
+    assert!(fasta.starts_with(">OPRN.1_ICR1 chr1:1501-3999(+) MSTRG.2>MSTRG.3\nCAGGACCCTGCC"));
+    assert!(fasta.contains(">OPRN.2_ICR2 chr1:10401-11799(-) MSTRG.7>MSTRG.6\nCTTCGACGGATC"));
+    let seq_len: usize = fasta.lines().skip(1).take_while(|l| !l.starts_with('>')).map(|l| l.len()).sum();
+    assert_eq!(seq_len, 2499);
+}
+
+#[test]
+fn test_spliced_sequences() {
+    let outdir = run_toy(".tests/test_spliced_sequences", &["--genome", "tests/resources/toy_genome.fa"]);
+    let containers = read_output(&outdir, "toy_operons_containers_t1.0.fa");
+    assert!(containers.starts_with(">MSTRG.1.1 OPRN.1 gene=MSTRG.1 chr1:1000-5000(+)\n"));
+    assert_eq!(containers.lines().filter(|l| l.starts_with('>')).count(), 3);
+
+    // Minus strand OpG: exons 10000-10100 and 10200-10400, reverse-complemented
+    let opgs = read_output(&outdir, "toy_operons_opgs_t1.0.fa");
+    assert!(opgs.contains(">MSTRG.6.1 OPRN.2 gene=MSTRG.6 rank=3 position=last chr1:10000-10400(-)\nTAAACTCCCCGTTTGCCATAGACC"));
+    let seq_len: usize = opgs
+        .lines()
+        .skip_while(|l| !l.starts_with(">MSTRG.6.1"))
+        .skip(1)
+        .take_while(|l| !l.starts_with('>'))
+        .map(|l| l.len())
+        .sum();
+    assert_eq!(seq_len, 302);
+}
+
+#[test]
+fn test_junction_support() {
+    let outdir = run_toy(
+        ".tests/test_junction_support",
+        &["--junctions", "tests/resources/toy_SJ.out.tab", "--min-bridging-reads", "5", "--explain"],
+    );
+    let support = read_output(&outdir, "toy_operons_junction_support_t1.0.tsv");
+    let rows = data_rows(&support);
+    // The intron of MSTRG.2.1 does not bridge two OpGs
+    assert_eq!(rows[0], "OPRN.1\tMSTRG.2.1\tMSTRG.3.1\tMSTRG.2\tMSTRG.3\t1\t12");
+    assert_eq!(rows[1], "OPRN.1\tMSTRG.3.1\tMSTRG.10.1\tMSTRG.3\tMSTRG.10\t1\t3");
+    assert_eq!(rows[2], "OPRN.2\tMSTRG.8.1\tMSTRG.7.1\tMSTRG.8\tMSTRG.7\t1\t6");
+
+    // OPRN.1 has a pair bridged by only 3 reads
+    let operons = data_rows(&read_output(&outdir, "toy_operons_operons_found_t1.0.tsv")).join("\n");
+    assert!(!operons.contains("OPRN.1\t"));
+    assert!(operons.contains("OPRN.2\tMSTRG.5.1\tMSTRG.6.1"));
+    // Its containers and claims are reported as removed
+    let explain = read_output(&outdir, "toy_operons_explain_t1.0.tsv");
+    assert!(explain.lines().any(|l| l.starts_with("MSTRG.1.1\t") && l.ends_with("\tOPRN.1\tjunction_support")));
+    assert!(explain.lines().any(|l| l.starts_with("MSTRG.5.1\t") && l.ends_with("\tOPRN.2\toperon")));
+    let conflicts = read_output(&outdir, "toy_operons_conflicts_t1.0.tsv");
+    assert!(data_rows(&conflicts).iter().all(|l| l.ends_with("\tdropped")));
+
+    let outdir = run_toy(".tests/test_junction_support_bed", &["--junctions", "tests/resources/toy_junctions.bed"]);
+    let support = read_output(&outdir, "toy_operons_junction_support_t1.0.tsv");
+    assert_eq!(data_rows(&support)[0], "OPRN.1\tMSTRG.2.1\tMSTRG.3.1\tMSTRG.2\tMSTRG.3\t1\t12");
+
+    // A block larger than the junction end is an error, not a wrapped coordinate
+    let bad = format!("{}/bad_junctions.bed", outdir);
+    std::fs::write(&bad, "chr1\t1450\t40\tJUNC00000001\t12\t+\t1450\t40\t255,0,0\t2\t50,50\t0,2549\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["-f", "tests/resources/toy_operons.gtf", "-o", &outdir, "--junctions", &bad])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("bad_junctions.bed:1: block size 50 larger than the end"));
+}
+
+#[test]
+fn test_longread_mode() {
+    let outdir = ".tests/test_longread_mode";
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["longread", "--bam", "tests/resources/toy_longreads.bam", "-a", "tests/resources/toy_genes.gtf", "-o", outdir])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+
+    // geneB-geneC is bridged by a single read: geneC stays out of OPRN.1
+    let operons = read_output(outdir, "toy_longreads_longread_operons.tsv");
+    let rows = data_rows(&operons);
+    assert_eq!(rows, vec!["OPRN.1\tchr1\t1000\t5000\t+\tgeneA,geneB\t4\t4", "OPRN.2\tchr1\t10000\t12300\t-\tgeneE,geneD\t2\t2"]);
+
+    // Unmapped, secondary and monocistronic reads are not molecules
+    let reads = read_output(outdir, "toy_longreads_longread_reads.tsv");
+    let reads = data_rows(&reads);
+    assert_eq!(reads.len(), 6);
+    assert!(reads.contains(&"read4\tchr1\t+\tgeneA,geneB,geneC\tOPRN.1"));
+}
+
+#[test]
+fn test_diff_usage() {
+    let outdir = run_toy(".tests/test_diff_usage", &[]);
+    // Two replicates per condition, with MSTRG.5.1 (OPRN.2 container) up in 'treated'
+    let toy = std::fs::read_to_string("tests/resources/toy_operons.gtf").unwrap();
+    let mut samples = Vec::new();
+    for (condition, name, fpkm) in [("ctrl", "ctrl1", "1.0"), ("ctrl", "ctrl2", "1.1"), ("treated", "treated1", "4.0"), ("treated", "treated2", "4.4")] {
+        let path = format!("{}/{}.gtf", outdir, name);
+        let sample = toy.replace("transcript_id \"MSTRG.5.1\"; cov \"4.0\"; FPKM \"1.0\";", &format!("transcript_id \"MSTRG.5.1\"; cov \"4.0\"; FPKM \"{}\";", fpkm));
+        std::fs::write(&path, sample).unwrap();
+        samples.push(format!("{}={}", condition, path));
+    }
+    let operons = format!("{}/toy_operons_operons_found_t1.0.tsv", outdir);
+    let mut command = Command::new(env!("CARGO_BIN_EXE_gamba"));
+    command.args(["diff", "--operons", &operons, "-o", &outdir]);
+    for sample in &samples {
+        command.args(["--sample", sample]);
+    }
+    let output = command.output().expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+
+    let diff = read_output(&outdir, "toy_operons_operons_found_t1.0_diff_usage.tsv");
+    assert!(diff.contains("Log2_ratio_ctrl\tLog2_ratio_treated\tLog2FC"));
+    let rows: Vec<Vec<&str>> = data_rows(&diff).iter().map(|l| l.split('\t').collect()).collect();
+    assert_eq!(rows.len(), 2);
+    // OPRN.1 is unchanged: no variance, no test
+    let oprn1 = rows.iter().find(|r| r[0] == "OPRN.1").unwrap();
+    assert_eq!((oprn1[5], oprn1[7]), ("0.0000", "NA"));
+    // OPRN.2: container/OpG ratio about four times higher
+    let oprn2 = rows.iter().find(|r| r[0] == "OPRN.2").unwrap();
+    assert_eq!((oprn2[1], oprn2[2]), ("MSTRG.5.1", "3"));
+    let log2fc: f64 = oprn2[5].parse().unwrap();
+    assert!((log2fc - 2.0).abs() < 0.05, "{}", log2fc);
+    let p: f64 = oprn2[7].parse().unwrap();
+    assert!(p < 0.05, "{}", p);
+
+    let ratios = read_output(&outdir, "toy_operons_operons_found_t1.0_diff_ratios.tsv");
+    assert_eq!(data_rows(&ratios).len(), 8);
+    assert!(ratios.contains("OPRN.2\ttreated1.gtf\ttreated\t4.0000\t8.0000\t-0.9982"));
+}
+
+#[test]
+fn test_coverage_from_bedgraph() {
+    // Toy annotation without 'cov' attributes: only chr1 10000-14500 is covered by the bedGraph
+    let outdir = ".tests/test_coverage_from_bedgraph";
+    std::fs::create_dir_all(outdir).unwrap();
+    let mut gtf = String::new();
+    for line in std::fs::read_to_string("tests/resources/toy_operons.gtf").unwrap().lines() {
+        let mut line = line.to_string();
+        while let Some(start) = line.find(" cov \"") {
+            let end = start + line[start..].find("\";").unwrap() + 2;
+            line.replace_range(start..end, "");
+        }
+        gtf.push_str(&line);
+        gtf.push('\n');
+    }
+    let path = format!("{}/toy_nocov.gtf", outdir);
+    std::fs::write(&path, gtf).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["-f", &path, "-o", outdir, "-t", "0.5", "--coverage-from", "tests/resources/toy_coverage.bedgraph"])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+
+    // MSTRG.5.1 averages its OpGs and the low-depth stretches in between (~16.5)
+    let operons = read_output(outdir, "toy_nocov_operons_found_t0.5.tsv");
+    let opgs: Vec<&str> = data_rows(&operons).iter().map(|l| l.split('\t').nth(2).unwrap()).collect();
+    assert_eq!(opgs, vec!["MSTRG.8.1", "MSTRG.7.1", "MSTRG.6.1"]);
+    assert!(operons.contains("OPRN.1\tMSTRG.5.1\tMSTRG.8.1\t1\tfirst\n"));
+    let genes = read_output(outdir, "toy_nocov_operon_genes_t0.5.tsv");
+    let coverage: Vec<&str> = data_rows(&genes).iter().map(|l| l.split('\t').nth(9).unwrap()).collect();
+    assert_eq!(coverage, vec!["10.0000", "20.0000", "30.0000"]);
+}
+
+#[test]
+fn test_expression_from_quant() {
+    let outdir = run_toy(".tests/test_expression_from_quant", &["--expression", "tests/resources/toy_quant.sf"]);
+
+    // Only the chr1 minus-strand transcripts are quantified: the other containers get 0
+    let operons = read_output(&outdir, "toy_operons_operons_found_t1.0.tsv");
+    let opgs: Vec<&str> = data_rows(&operons).iter().map(|l| l.split('\t').nth(2).unwrap()).collect();
+    assert_eq!(opgs, vec!["MSTRG.8.1", "MSTRG.7.1", "MSTRG.6.1"]);
+    let genes = read_output(&outdir, "toy_operons_operon_genes_t1.0.tsv");
+    let fpkm: Vec<&str> = data_rows(&genes).iter().map(|l| l.split('\t').nth(10).unwrap()).collect();
+    assert_eq!(fpkm, vec!["10.0000", "20.0000", "30.0000"]);
+
+    let unmatched = read_output(&outdir, "toy_operons_expression_unmatched.tsv");
+    let rows = data_rows(&unmatched);
+    assert_eq!(rows.len(), 12);
+    assert!(rows.contains(&"MSTRG.1.1\ttable"));
+    assert!(rows.contains(&"MSTRG.99.1\tannotation"));
+}
+
+#[test]
+fn test_polarity() {
+    let outdir = run_toy(".tests/test_polarity", &[]);
+    let content = read_output(&outdir, "toy_operons_polarity_t1.0.tsv");
+    let rows = data_rows(&content);
+    // OPRN.1: FPKM 20 > 15 > 9; OPRN.2 (minus strand, 5'→3'): 4 < 8 < 12
+    assert_eq!(rows[0], "OPRN.1\t+\t3\t20.0000,15.0000,9.0000\t-0.5756\t1.1511\t-1.0000\t2/2\tdecreasing");
+    assert!(rows[1].starts_with("OPRN.2\t-\t3\t4.0000,8.0000,12.0000\t0.7913\t"));
+    assert!(rows[1].ends_with("\t0/2\tincreasing"));
+
+    let coverage = run_toy(".tests/test_polarity_coverage", &["--polarity-measure", "coverage"]);
+    let summary = read_output(&coverage, "toy_operons_polarity_summary_t1.0.tsv");
+    let rows = data_rows(&summary);
+    assert!(rows.contains(&"Measure\tCoverage"));
+    assert!(rows.contains(&"Trend_decreasing\t1"));
+    assert!(rows.contains(&"Negative_slope\t1"));
+
+    // No OPRN: the averages are not available
+    let empty = run_toy(".tests/test_polarity_empty", &["-t", "100000"]);
+    let summary = read_output(&empty, "toy_operons_polarity_summary_t100000.0.tsv");
+    let rows = data_rows(&summary);
+    assert!(rows.contains(&"OPRNs\t0"));
+    assert!(rows.contains(&"Median_log2_slope\tNA"));
+    assert!(rows.contains(&"Mean_log2_slope\tNA"));
+    assert!(rows.contains(&"Mean_spearman_rho\tNA"));
+
+    // Genes at the same depth: flat, not mixed
+    let flat = ".tests/test_polarity_flat";
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args([
+            "-f", "tests/resources/toy_bacteria.gtf", "-o", flat, "--prokaryote", "--genes", "tests/resources/toy_bacteria_genes.gtf",
+            "--coverage-from", "tests/resources/toy_bacteria.bedgraph",
+        ])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+    let polarity = read_output(flat, "toy_bacteria_polarity_t1.0.tsv");
+    assert!(data_rows(&polarity).iter().all(|l| l.ends_with("\tflat")));
+    let summary = read_output(flat, "toy_bacteria_polarity_summary_t1.0.tsv");
+    let rows = data_rows(&summary);
+    assert!(rows.contains(&"Trend_flat\t3"));
+    assert!(rows.contains(&"Trend_mixed\t0"));
+}
+
+#[test]
+fn test_prokaryote_mode() {
+    let outdir = ".tests/test_prokaryote_mode";
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args([
+            "-f", "tests/resources/toy_bacteria.gtf", "-o", outdir, "--prokaryote", "--genes", "tests/resources/toy_bacteria_genes.gtf",
+            "--coverage-from", "tests/resources/toy_bacteria.bedgraph", "--explain",
+        ])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+
+    // Single-exon transcripts spanning annotated genes; the minus-strand operon is listed 5'→3'
+    let operons = read_output(outdir, "toy_bacteria_operons_found_t1.0.tsv");
+    let rows: Vec<String> = data_rows(&operons).iter().map(|l| l.split('\t').take(3).collect::<Vec<_>>().join("\t")).collect();
+    assert_eq!(rows, vec![
+        "OPRN.1\tMSTRG.1.1\tgA",
+        "OPRN.1\tMSTRG.1.1\tgB",
+        "OPRN.1\tMSTRG.1.1\tgC",
+        "OPRN.2\tMSTRG.2.1\tgD",
+        "OPRN.2\tMSTRG.2.1\tgE",
+        "OPRN.3\tMSTRG.3.1\tgG",
+        "OPRN.3\tMSTRG.3.1\tgF",
+    ]);
+
+    // gC and gD are 899 bp apart; gH is the only gene of MSTRG.5.1
+    let explain = read_output(outdir, "toy_bacteria_explain_t1.0.tsv");
+    assert!(explain.lines().any(|l| l.starts_with("MSTRG.4.1\t") && l.ends_with("\tintergenic_gap")));
+    assert!(explain.lines().any(|l| l.starts_with("MSTRG.5.1\t") && l.ends_with("\tfew_contained")));
+
+    // The OpGs are written with their annotation records, gene spans from the CDS
+    let genes_gtf = read_output(outdir, "toy_bacteria_OperonGenes_t1.0.gtf");
+    assert!(genes_gtf.contains("chrB\tRefSeq\tCDS\t100\t900\t.\t+\t0\tgene_id \"gA\";transcript_id \"gA.t1\";opg_rank \"1\";opg_position \"first\";"));
+    let genes = read_output(outdir, "toy_bacteria_operon_genes_t1.0.tsv");
+    assert!(data_rows(&genes)[0].starts_with("OPRN.1\t1\tfirst\tgA\tchrB\t100\t900\t+"));
+    // Genes have a depth but no FPKM: polarity on coverage
+    assert!(data_rows(&genes)[0].ends_with("\tgA\t50.0000\tNA"));
+    let polarity = read_output(outdir, "toy_bacteria_polarity_t1.0.tsv");
+    assert!(data_rows(&polarity)[0].starts_with("OPRN.1\t+\t3\t50.0000,50.0000,50.0000\t"));
+    let summary = read_output(outdir, "toy_bacteria_polarity_summary_t1.0.tsv");
+    assert!(data_rows(&summary).contains(&"Measure\tCoverage"));
+
+    // Genes at depth 50 are not above twice the coverage of their containers (33 to 47)
+    let strict = ".tests/test_prokaryote_mode_threshold";
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args([
+            "-f", "tests/resources/toy_bacteria.gtf", "-o", strict, "--prokaryote", "--genes", "tests/resources/toy_bacteria_genes.gtf",
+            "--coverage-from", "tests/resources/toy_bacteria.bedgraph", "-t", "2",
+        ])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(data_rows(&read_output(strict, "toy_bacteria_operons_found_t2.0.tsv")).is_empty());
+
+    // The exon-based criteria do not apply to genes
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args([
+            "-f", "tests/resources/toy_bacteria.gtf", "-o", strict, "--prokaryote", "--genes", "tests/resources/toy_bacteria_genes.gtf",
+            "--coverage-from", "tests/resources/toy_bacteria.bedgraph", "--monoexonic-t", "2",
+        ])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_predict_from_annotation() {
+    let outdir = ".tests/test_predict_from_annotation";
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["predict", "-f", "tests/resources/toy_bacteria_genes.gtf", "-o", outdir])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+
+    // gC-gD are 899 bp apart, gE-gF and gG-gH change strand; gH is left alone
+    let operons = read_output(outdir, "toy_bacteria_genes_operons_found_predicted.tsv");
+    let rows: Vec<String> = data_rows(&operons).iter().map(|l| l.split('\t').take(3).collect::<Vec<_>>().join("\t")).collect();
+    assert_eq!(rows, vec![
+        "OPRN.1\t.\tgA",
+        "OPRN.1\t.\tgB",
+        "OPRN.1\t.\tgC",
+        "OPRN.2\t.\tgD",
+        "OPRN.2\t.\tgE",
+        "OPRN.3\t.\tgG",
+        "OPRN.3\t.\tgF",
+    ]);
+    let clean = read_output(outdir, "toy_bacteria_genes_opCLEAN_predicted.gtf");
+    assert!(clean.contains("gene_id \"gH\""));
+
+    // No expression: no polarity tables, and no score or expression values
+    assert!(!Path::new(outdir).join("toy_bacteria_genes_polarity_predicted.tsv").exists());
+    assert!(!Path::new(outdir).join("toy_bacteria_genes_polarity_summary_predicted.tsv").exists());
+    let features = read_output(outdir, "toy_bacteria_genes_operon_features_predicted.gtf");
+    assert!(features.contains("chrB\tgamba\toperon\t100\t2600\t.\t+\t."));
+    let genes = read_output(outdir, "toy_bacteria_genes_operon_genes_predicted.tsv");
+    assert!(data_rows(&genes)[0].ends_with("\tNA\tNA"));
+
+    // A looser spacing merges the first two runs, only partly supported by the prokaryotic calls
+    let calls_outdir = ".tests/test_predict_from_annotation/calls";
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args([
+            "-f", "tests/resources/toy_bacteria.gtf", "-o", calls_outdir, "--prokaryote", "--genes", "tests/resources/toy_bacteria_genes.gtf",
+            "--coverage-from", "tests/resources/toy_bacteria.bedgraph",
+        ])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args([
+            "predict", "-f", "tests/resources/toy_bacteria_genes.gtf", "-o", outdir, "-p", "loose", "--max-intergenic", "1000",
+            "--calls", &format!("{}/toy_bacteria_operons_found_t1.0.tsv", calls_outdir),
+        ])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+    let comparison = read_output(outdir, "loose_predicted_vs_calls.tsv");
+    assert_eq!(data_rows(&comparison), vec![
+        "OPRN.1\tgA,gB,gC,gD,gE\tOPRN.1,OPRN.2\t3\tpartial",
+        "OPRN.2\tgG,gF\tOPRN.3\t2\tfull",
+    ]);
+}
+
+#[test]
+fn test_compare_species() {
+    let outdir = ".tests/test_compare_species";
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args([
+            "compare",
+            "--operons-a", "tests/resources/toy_species_a_operon_genes.tsv",
+            "--operons-b", "tests/resources/toy_species_b_operon_genes.tsv",
+            "--orthologs", "tests/resources/toy_orthologs.tsv",
+            "--name-a", "oikA", "--name-b", "oikB",
+            "-o", outdir, "-p", "toy",
+        ])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+
+    // a2-a3 has orthologs not adjacent in oikB; a6, a7 and b12 have no ortholog; b9-b8 is a8-a9 reversed
+    let operons = read_output(outdir, "toy_conserved_operons.tsv");
+    assert_eq!(data_rows(&operons), vec![
+        "oikA\tOPRN.1\ta1,a2,a3\t2\t1\tOPRN.1\tpartial",
+        "oikA\tOPRN.2\ta4,a5\t1\t0\t.\tlineage_specific",
+        "oikA\tOPRN.3\ta6,a7\t1\t0\t.\tno_orthologs",
+        "oikA\tOPRN.4\ta8,a9\t1\t1\tOPRN.3\tconserved",
+        "oikB\tOPRN.1\tb1,b2\t1\t1\tOPRN.1\tconserved",
+        "oikB\tOPRN.2\tb3,b12\t1\t0\t.\tno_orthologs",
+        "oikB\tOPRN.3\tb9,b8\t1\t1\tOPRN.4\tconserved",
+    ]);
+    let pairs = read_output(outdir, "toy_conserved_pairs.tsv");
+    assert!(data_rows(&pairs).contains(&"oikB\tOPRN.3\tb9\tb8\ta9\ta8\tOPRN.4\tconserved"));
+
+    let summary = read_output(outdir, "toy_conservation_summary.tsv");
+    let rows = data_rows(&summary);
+    assert!(rows.contains(&"Pairs_with_orthologs\t4\t2"));
+    assert!(rows.contains(&"Pair_conservation\t0.5000\t1.0000"));
+}
+
+#[test]
+fn test_convert_bed_edge_cases() {
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["convert", "-f", "tests/resources/toy_convert_edges.gtf", "--to", "bed"])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // An exon starting before its transcript record widens the BED line
+    assert!(stdout.lines().any(|l| l == "chr1\t899\t5000\tG1.1\t0\t+\t899\t5000\t0\t2\t601,501,\t0,3600,"));
+    // A transcript without exon records is one block
+    assert!(stdout.lines().any(|l| l == "chr1\t5999\t6500\tG2.1\t0\t-\t5999\t6500\t0\t1\t501,\t0,"));
+}
+
+#[test]
+fn test_duplicate_records_plain() {
+    // Only indexed reads drop repeated lines; a plain file is read as is
+    let outdir = ".tests/test_duplicate_records_plain";
+    std::fs::create_dir_all(outdir).unwrap();
+    let gtf = std::fs::read_to_string("tests/resources/toy_operons.gtf").unwrap();
+    let exon = gtf.lines().nth(1).unwrap();
+    let duplicated_path = format!("{}/duplicated.gtf", outdir);
+    std::fs::write(&duplicated_path, gtf.replacen(exon, &format!("{}\n{}", exon, exon), 1)).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gamba"))
+        .args(["convert", "-f", &duplicated_path, "--to", "gff3"])
+        .output()
+        .expect("Failed to execute gamba_tool binary");
+    assert!(output.status.success(), "Program exited with error: {}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().filter(|l| l.contains("\texon\t1000\t1500\t")).count(), 2);
+}